@@ -1,6 +0,0 @@
-
-extern crate mptree;
-
-pub fn main() {
-    mptree::header::max_frame_size();
-}
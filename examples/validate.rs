@@ -0,0 +1,35 @@
+extern crate mptree;
+
+use mptree::validate;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: validate <file.mp3>");
+            process::exit(2);
+        },
+    };
+
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let report = match validate::validate(&mut reader) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(2);
+        },
+    };
+
+    for problem in report.problems() {
+        println!("{}: {}", path, problem);
+    }
+    println!("{}: {} frames, {} problems", path, report.frames(), report.problems().len());
+
+    if !report.is_valid() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,73 @@
+#!/bin/sh
+# Collects the ISO/IEC 11172-4 and 13818-4 compliance bitstreams and their reference outputs for
+# tests/conformance.rs. ISO doesn't allow redistributing them, so point this at your copy of the
+# electronic inserts: URLs, zip/tar archives or already unpacked directories.
+#
+#     scripts/fetch_conformance.sh <url-or-path>...
+#
+# Every `<name>.bit` with a `<name>.pcm` reference next to it is copied into
+# mpeg_compliance_testing/ (or $MPTREE_CONFORMANCE_DIR), keeping the source's directory layout
+# and lowercasing the extensions. Then run the comparison with
+#
+#     cargo test --test conformance -- --ignored
+set -eu
+
+if [ $# -eq 0 ]; then
+    sed -n '2,12s/^# \{0,1\}//p' "$0" >&2
+    exit 2
+fi
+
+dest=${MPTREE_CONFORMANCE_DIR:-mpeg_compliance_testing}
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+count=0
+index=0
+for source in "$@"; do
+    index=$((index + 1))
+    case $source in
+        http://*|https://*|ftp://*)
+            file="$work/download-$index-$(basename "$source")"
+            curl -fL --retry 3 -o "$file" "$source"
+            source=$file
+            ;;
+    esac
+
+    if [ -d "$source" ]; then
+        root=$source
+    else
+        root="$work/unpacked-$index"
+        mkdir -p "$root"
+        case $source in
+            *.zip|*.ZIP) unzip -q -o "$source" -d "$root" ;;
+            *.tar|*.tar.gz|*.tgz|*.tar.bz2|*.tar.xz) tar -xf "$source" -C "$root" ;;
+            *) echo "don't know how to unpack $source" >&2; exit 1 ;;
+        esac
+    fi
+
+    # Relative paths, so the layout under `root` is kept.
+    for bit in $(cd "$root" && find . -type f \( -name '*.bit' -o -name '*.BIT' \) | sort); do
+        stem=${bit%.*}
+        pcm=
+        for extension in pcm PCM; do
+            if [ -f "$root/$stem.$extension" ]; then
+                pcm="$root/$stem.$extension"
+            fi
+        done
+        if [ -z "$pcm" ]; then
+            echo "skipping $bit: no reference output" >&2
+            continue
+        fi
+
+        mkdir -p "$dest/$(dirname "$stem")"
+        cp "$root/$bit" "$dest/$stem.bit"
+        cp "$pcm" "$dest/$stem.pcm"
+        count=$((count + 1))
+    done
+done
+
+if [ $count -eq 0 ]; then
+    echo "no compliance streams with reference output found" >&2
+    exit 1
+fi
+echo "copied $count streams to $dest"
@@ -0,0 +1,166 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str;
+
+use ::tags::APE_FOOTER_SIZE;
+
+const APE_VERSION: u32 = 2000;
+
+// Header and footer flags.
+const HAS_HEADER: u32 = 1 << 31;
+const IS_HEADER: u32 = 1 << 29;
+
+// Item flags, bits 1-2 hold the value type.
+const ITEM_TYPE_MASK: u32 = 0b110;
+const ITEM_TEXT: u32 = 0b000;
+
+// An APEv2 tag, as used by mp3gain and some taggers at the end of MP3 files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApeTag {
+    items: Vec<ApeItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApeItem {
+    key: String, // ASCII, compared case-insensitively
+    flags: u32,
+    value: Vec<u8>, // UTF-8 for text items
+}
+
+impl ApeTag {
+    pub fn new() -> ApeTag {
+        ApeTag::default()
+    }
+
+    // Parses a whole tag, the footer and, if flagged, the header in front of the items, such as
+    // the range found by `Tags::locate`.
+    pub fn parse(data: &[u8]) -> Option<ApeTag> {
+        let footer_size = APE_FOOTER_SIZE as usize;
+        if data.len() < footer_size {
+            return None;
+        }
+
+        let footer = &data[data.len() - footer_size..];
+        if &footer[0..8] != b"APETAGEX" {
+            return None;
+        }
+
+        let size = u32_at(footer, 12) as usize;
+        let count = u32_at(footer, 16);
+        if size < footer_size || size > data.len() {
+            return None;
+        }
+
+        let mut items = &data[data.len() - size..data.len() - footer_size];
+        let mut tag = ApeTag::new();
+        for _ in 0..count {
+            if items.len() < 9 {
+                return None;
+            }
+
+            let value_size = u32_at(items, 0) as usize;
+            let flags = u32_at(items, 4);
+            let key_end = 8 + items[8..].iter().position(|&byte| byte == 0)?;
+            let key = str::from_utf8(&items[8..key_end]).ok()?;
+            let value = items.get(key_end + 1..key_end + 1 + value_size)?;
+
+            tag.items.push(ApeItem {
+                key: String::from(key),
+                flags,
+                value: value.to_vec(),
+            });
+            items = &items[key_end + 1 + value_size..];
+        }
+
+        Some(tag)
+    }
+
+    #[inline]
+    pub fn items(&self) -> &[ApeItem] {
+        &self.items
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ApeItem> {
+        self.items.iter().find(|item| item.key.eq_ignore_ascii_case(key))
+    }
+
+    // Value of a text item.
+    pub fn text(&self, key: &str) -> Option<&str> {
+        self.get(key).filter(|item| item.is_text()).and_then(|item| str::from_utf8(&item.value).ok())
+    }
+
+    // Sets a text item, replacing any item with the same key.
+    pub fn set_text(&mut self, key: &str, value: &str) {
+        let item = ApeItem {
+            key: String::from(key),
+            flags: ITEM_TEXT,
+            value: value.as_bytes().to_vec(),
+        };
+
+        match self.items.iter_mut().find(|item| item.key.eq_ignore_ascii_case(key)) {
+            Some(existing) => *existing = item,
+            None => self.items.push(item),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<ApeItem> {
+        let index = self.items.iter().position(|item| item.key.eq_ignore_ascii_case(key))?;
+        Some(self.items.remove(index))
+    }
+
+    // Serializes the tag with both a header and a footer.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut items = Vec::new();
+        for item in &self.items {
+            items.extend_from_slice(&(item.value.len() as u32).to_le_bytes());
+            items.extend_from_slice(&item.flags.to_le_bytes());
+            items.extend_from_slice(item.key.as_bytes());
+            items.push(0);
+            items.extend_from_slice(&item.value);
+        }
+
+        let size = (items.len() + APE_FOOTER_SIZE as usize) as u32;
+        let count = self.items.len() as u32;
+        let mut data = Vec::with_capacity(items.len() + 2 * APE_FOOTER_SIZE as usize);
+        data.extend_from_slice(&header_or_footer(size, count, HAS_HEADER | IS_HEADER));
+        data.extend_from_slice(&items);
+        data.extend_from_slice(&header_or_footer(size, count, HAS_HEADER));
+        data
+    }
+}
+
+impl ApeItem {
+    #[inline]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    #[inline]
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    #[inline]
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    #[inline]
+    pub fn is_text(&self) -> bool {
+        self.flags & ITEM_TYPE_MASK == ITEM_TEXT
+    }
+}
+
+fn header_or_footer(size: u32, count: u32, flags: u32) -> [u8; 32] {
+    let mut data = [0u8; 32];
+    data[0..8].copy_from_slice(b"APETAGEX");
+    data[8..12].copy_from_slice(&APE_VERSION.to_le_bytes());
+    data[12..16].copy_from_slice(&size.to_le_bytes());
+    data[16..20].copy_from_slice(&count.to_le_bytes());
+    data[20..24].copy_from_slice(&flags.to_le_bytes());
+    data
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
@@ -0,0 +1,76 @@
+use std::fmt::{Display, Write};
+
+// Minimal JSON object writer, enough for flat reports without pulling in serde.
+pub struct Object {
+    out: String,
+    empty: bool,
+}
+
+impl Object {
+    pub fn new() -> Object {
+        Object {
+            out: String::from("{"),
+            empty: true,
+        }
+    }
+
+    pub fn string(self, key: &str, value: &str) -> Object {
+        let value = string(value);
+        self.raw(key, &value)
+    }
+
+    pub fn number<N: Display>(self, key: &str, value: N) -> Object {
+        self.raw(key, &value.to_string())
+    }
+
+    pub fn boolean(self, key: &str, value: bool) -> Object {
+        self.raw(key, if value { "true" } else { "false" })
+    }
+
+    pub fn null(self, key: &str) -> Object {
+        self.raw(key, "null")
+    }
+
+    // Adds an already serialized value, such as a nested object or an array.
+    pub fn raw(mut self, key: &str, value: &str) -> Object {
+        if !self.empty {
+            self.out.push(',');
+        }
+        self.empty = false;
+        self.out.push_str(&string(key));
+        self.out.push(':');
+        self.out.push_str(value);
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push('}');
+        self.out
+    }
+}
+
+// Serializes already serialized values as an array.
+pub fn array<I: IntoIterator<Item = String>>(values: I) -> String {
+    let values: Vec<String> = values.into_iter().collect();
+    format!("[{}]", values.join(","))
+}
+
+pub fn string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
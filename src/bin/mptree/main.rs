@@ -0,0 +1,689 @@
+extern crate mptree;
+
+mod json;
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::process;
+use std::str;
+use std::time::Duration;
+
+use mptree::ape::ApeTag;
+use mptree::decoder::{Frame, FrameReader};
+use mptree::edit;
+use mptree::error::{ErrorKind, MpError};
+use mptree::header::{ChannelMode, Extension, Header, Layer, Version};
+use mptree::id3::{Id3Frame, Id3Tag};
+use mptree::probe::{self, Scan, StreamInfo};
+use mptree::scalefactors::{self, Scalefactors};
+use mptree::side_info::BlockType;
+use mptree::source::Bounded;
+use mptree::tags::Tags;
+use mptree::validate::{self, Problem};
+use mptree::vbr::{BitrateMode, VbrHeader};
+
+use json::Object;
+
+const USAGE: &str = "usage:
+    mptree info [--json] [--fast | --sample <frames>] <file>
+    mptree decode [--raw] <file>
+    mptree validate [--json] <file>
+    mptree dump [--json] <file>
+    mptree repair [--no-gapless] <file>
+
+info      stream format, duration, VBR header and tags
+decode    not supported yet, fails as there is no PCM decoder; would write WAV
+          (or raw PCM with --raw) to stdout
+validate  report junk, sync loss, format changes, CRC and reservoir problems
+dump      headers, side information, scalefactors and reservoir use of every frame
+repair    rewrite the Xing/Info header from the frames, in place; --no-gapless
+          drops the LAME header instead of keeping its delay and padding
+
+--json prints JSON (one object per frame for dump). Exits with 1 when
+validate finds problems and 2 on usage or read errors.";
+
+// Command line options shared by the subcommands, not all of them apply to each.
+struct Options {
+    json: bool,
+    scan: Scan,
+    gapless: bool,
+    raw: bool,
+    path: String,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first() {
+        Some(command) => command.as_str(),
+        None => usage(),
+    };
+    let options = parse_options(&args[1..]);
+
+    let result = match command {
+        "info" => info(&options),
+        "decode" => decode(&options),
+        "validate" => validate(&options),
+        "dump" => dump(&options),
+        "repair" => repair(&options),
+        _ => usage(),
+    };
+
+    match result {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("mptree: {}: {}", options.path, err);
+            process::exit(2);
+        },
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_options(args: &[String]) -> Options {
+    let mut options = Options {
+        json: false,
+        scan: Scan::Full,
+        gapless: true,
+        raw: false,
+        path: String::new(),
+    };
+
+    let mut args = args.iter();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--fast" => options.scan = Scan::Bitrate,
+            "--sample" => match args.next().and_then(|frames| frames.parse().ok()) {
+                Some(frames) => options.scan = Scan::Sample(frames),
+                None => usage(),
+            },
+            "--no-gapless" => options.gapless = false,
+            "--raw" => options.raw = true,
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with('-') => usage(),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => usage(),
+        }
+    }
+
+    options.path = match path {
+        Some(path) => path,
+        None => usage(),
+    };
+    options
+}
+
+fn open(path: &str) -> Result<BufReader<File>, MpError> {
+    Ok(BufReader::new(File::open(path)?))
+}
+
+fn info(options: &Options) -> Result<bool, MpError> {
+    let mut reader = open(&options.path)?;
+    let info = probe::probe(&mut reader, options.scan)?;
+    let fields = TagFields::read(&mut reader, info.tags())?;
+
+    if options.json {
+        println!("{}", info_json(&options.path, &info, &fields));
+        return Ok(true);
+    }
+
+    println!("file:      {}", options.path);
+    println!(
+        "format:    MPEG {} Layer {}, {} Hz, {}",
+        version_name(info.version()), layer_number(info.layer()), info.sampling_rate(), channel_name(info.channel()),
+    );
+    if info.min_bitrate() == info.max_bitrate() {
+        println!("bitrate:   {} kbps ({})", info.max_bitrate(), bitrate_mode_name(info.bitrate_mode()));
+    } else {
+        println!(
+            "bitrate:   {}-{} kbps ({})",
+            info.min_bitrate(), info.max_bitrate(), bitrate_mode_name(info.bitrate_mode()),
+        );
+    }
+    println!("frames:    {}", info.frames());
+    println!(
+        "duration:  {}{}",
+        format_duration(info.duration()),
+        if info.duration_method().is_exact() { "" } else { " (estimated)" },
+    );
+    match info.vbr_header() {
+        Some(VbrHeader::Xing(xing)) => {
+            let name = if xing.info() { "Info" } else { "Xing" };
+            match xing.lame() {
+                Some(lame) => println!(
+                    "vbr:       {} ({}, delay {}, padding {})",
+                    name, encoder_name(lame.encoder()), lame.encoder_delay(), lame.padding(),
+                ),
+                None => println!("vbr:       {}", name),
+            }
+        },
+        Some(VbrHeader::Vbri(_)) => println!("vbr:       VBRI"),
+        None => (),
+    }
+
+    let tags = info.tags();
+    if let Some(id3v2) = tags.id3v2() {
+        println!("id3v2:     ID3v2.{} at {}..{}", tags.id3v2_version(), id3v2.start, id3v2.end);
+        print_fields(&fields.id3v2);
+    }
+    if let Some(ape) = tags.ape() {
+        println!("ape:       APEv2 at {}..{}", ape.start, ape.end);
+        print_fields(&fields.ape);
+    }
+    if let Some(id3v1) = tags.id3v1() {
+        println!("id3v1:     ID3v1 at {}..{}", id3v1.start, id3v1.end);
+        print_fields(&fields.id3v1);
+    }
+    println!("audio:     {}..{}", tags.audio().start, tags.audio().end);
+    Ok(true)
+}
+
+fn print_fields(fields: &Option<Vec<(String, String)>>) {
+    match *fields {
+        Some(ref fields) => for (name, value) in fields {
+            println!("           {:<6} {}", name, value);
+        },
+        None => println!("           (malformed)"),
+    }
+}
+
+// There is no PCM decoder yet, so this only checks that the file opens.
+fn decode(options: &Options) -> Result<bool, MpError> {
+    open(&options.path)?;
+    eprintln!(
+        "mptree: {}: decoding to {} is not supported: no PCM decoder",
+        options.path, if options.raw { "raw PCM" } else { "WAV" },
+    );
+    process::exit(2);
+}
+
+fn validate(options: &Options) -> Result<bool, MpError> {
+    let mut reader = open(&options.path)?;
+    let report = validate::validate(&mut reader)?;
+
+    if options.json {
+        println!("{}", Object::new()
+            .string("file", &options.path)
+            .number("frames", report.frames())
+            .boolean("valid", report.is_valid())
+            .raw("problems", &json::array(report.problems().iter().map(problem_json)))
+            .finish());
+    } else {
+        for problem in report.problems() {
+            println!("{}: {}", options.path, problem);
+        }
+        println!("{}: {} frames, {} problems", options.path, report.frames(), report.problems().len());
+    }
+
+    Ok(report.is_valid())
+}
+
+fn dump(options: &Options) -> Result<bool, MpError> {
+    let mut reader = open(&options.path)?;
+    let tags = Tags::locate(&mut reader)?;
+    let mut frame_reader = FrameReader::new(Bounded::new(&mut reader, tags.audio().end)?);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    loop {
+        let frame = match frame_reader.advance() {
+            Ok(frame) => frame,
+            Err(err) => match *err.kind() {
+                ErrorKind::EOF => break,
+                ErrorKind::TruncatedFrame => {
+                    eprintln!("mptree: {}: {}", options.path, err);
+                    break;
+                },
+                _ if err.is_recoverable() => {
+                    eprintln!("mptree: {}: {}", options.path, err);
+                    continue;
+                },
+                _ => return Err(err),
+            },
+        };
+
+        let line = if options.json { frame_json(&frame) } else { frame_text(&frame) };
+        if writeln!(out, "{}", line).is_err() {
+            break; // Closed pipe, such as `mptree dump file.mp3 | head`
+        }
+    }
+
+    Ok(true)
+}
+
+fn repair(options: &Options) -> Result<bool, MpError> {
+    let mut file = OpenOptions::new().read(true).write(true).open(&options.path)?;
+    let summary = edit::repair_vbr_header(&mut file, options.gapless)?;
+    println!("{}: {} frames, {} bytes, encoder delay {}, padding {}",
+        options.path, summary.frames(), summary.bytes(), summary.encoder_delay(), summary.padding());
+    Ok(true)
+}
+
+fn frame_text(frame: &Frame) -> String {
+    let header = frame.header();
+    let side_information = frame.side_information();
+    let mut text = format!(
+        "{:6} @ {:9}: MPEG {} Layer {} {:3} kbps {:5} Hz {:12} main_data_begin {:3} main_data {:4} reservoir {:3} used {:5} bits{}{}",
+        frame.number(),
+        frame.offset(),
+        version_name(header.version()),
+        layer_number(header.layer()),
+        header.bitrate(),
+        header.sampling_rate(),
+        channel_name(header.channel()),
+        side_information.main_data_begin(),
+        side_information.main_data_size(),
+        frame.reservoir(),
+        side_information.main_data_bits(),
+        if frame.silent() { " silent" } else { "" },
+        if frame.format_changed() && frame.number() > 0 { " format changed" } else { "" },
+    );
+
+    let scalefactors = read_scalefactors(frame);
+    for (gr, granule) in side_information.granules().iter().enumerate() {
+        for ch in 0..side_information.channels() {
+            let channel = granule.channel(ch);
+            let table_select = channel.table_select();
+            let subblock_gain = channel.subblock_gain();
+            text.push_str(&format!(
+                "\n         gr {} ch {}: part2_3_length {:4} big_values {:3} global_gain {:3} scalefac_compress {:2} \
+                 block_type {} mixed {} table_select {},{},{} regions {},{} subblock_gain {},{},{} preflag {} \
+                 scalefac_scale {} count1table {}",
+                gr, ch,
+                channel.part2_3_length(),
+                channel.big_values(),
+                channel.global_gain(),
+                channel.scalefactor_compress(),
+                block_type_name(channel.block_type()),
+                channel.mixed_block() as u8,
+                table_select[0], table_select[1], table_select[2],
+                channel.region0_count(), channel.region1_count(),
+                subblock_gain[0], subblock_gain[1], subblock_gain[2],
+                channel.preflag() as u8,
+                channel.scalefactor_scale(),
+                channel.count1table_select(),
+            ));
+
+            match scalefactors {
+                Ok(ref scalefactors) => {
+                    let scalefactors = &scalefactors[gr][ch];
+                    let long: Vec<String> = scalefactors.long().iter().map(|value| value.to_string()).collect();
+                    let short: Vec<String> = scalefactors.short().iter()
+                        .map(|windows| format!("{}/{}/{}", windows[0], windows[1], windows[2]))
+                        .collect();
+                    text.push_str(&format!("\n                   long {}", long.join(" ")));
+                    if channel.windows_switching() && channel.block_type() == BlockType::Short {
+                        text.push_str(&format!("\n                   short {}", short.join(" ")));
+                    }
+                },
+                Err(ref err) => text.push_str(&format!("\n                   scalefactors: {}", err)),
+            }
+        }
+    }
+
+    text
+}
+
+fn frame_json(frame: &Frame) -> String {
+    let header = frame.header();
+    let side_information = frame.side_information();
+    let scalefactors = read_scalefactors(frame);
+
+    let granules = side_information.granules().iter().enumerate().map(|(gr, granule)| {
+        json::array((0..side_information.channels()).map(|ch| {
+            let channel = granule.channel(ch);
+            let scalefactors = match scalefactors {
+                Ok(ref scalefactors) => {
+                    let scalefactors = &scalefactors[gr][ch];
+                    Object::new()
+                        .number("length", scalefactors.length())
+                        .raw("long", &json::array(scalefactors.long().iter().map(|value| value.to_string())))
+                        .raw("short", &json::array(scalefactors.short().iter().map(|windows| {
+                            json::array(windows.iter().map(|value| value.to_string()))
+                        })))
+                        .finish()
+                },
+                Err(_) => String::from("null"),
+            };
+
+            Object::new()
+                .number("part2_3_length", channel.part2_3_length())
+                .number("big_values", channel.big_values())
+                .number("global_gain", channel.global_gain())
+                .number("scalefactor_compress", channel.scalefactor_compress())
+                .boolean("windows_switching", channel.windows_switching())
+                .string("block_type", block_type_name(channel.block_type()))
+                .boolean("mixed_block", channel.mixed_block())
+                .raw("table_select", &json::array(channel.table_select().iter().map(|value| value.to_string())))
+                .raw("subblock_gain", &json::array(channel.subblock_gain().iter().map(|value| value.to_string())))
+                .number("region0_count", channel.region0_count())
+                .number("region1_count", channel.region1_count())
+                .boolean("preflag", channel.preflag())
+                .number("scalefactor_scale", channel.scalefactor_scale())
+                .number("count1table_select", channel.count1table_select())
+                .raw("scalefactors", &scalefactors)
+                .finish()
+        }))
+    });
+
+    let scfsi = json::array(side_information.scfsi().iter().take(side_information.channels()).map(|bands| {
+        json::array(bands.iter().map(|&reused| String::from(if reused { "true" } else { "false" })))
+    }));
+
+    // Bits of this frame's main data (borrowed and own) that the granules leave unused.
+    let main_data_bits = (side_information.main_data_begin() as i64 + side_information.main_data_size() as i64) * 8;
+    let reservoir = Object::new()
+        .number("available", frame.reservoir())
+        .number("borrowed", side_information.main_data_begin())
+        .number("own", side_information.main_data_size())
+        .number("used_bits", side_information.main_data_bits())
+        .number("unused_bits", main_data_bits - side_information.main_data_bits() as i64)
+        .finish();
+
+    let object = Object::new()
+        .number("frame", frame.number())
+        .number("offset", frame.offset())
+        .raw("header", &header_json(header))
+        .raw("side_information", &Object::new()
+            .number("main_data_begin", side_information.main_data_begin())
+            .number("main_data_size", side_information.main_data_size())
+            .raw("scfsi", &scfsi)
+            .raw("granules", &json::array(granules))
+            .finish())
+        .raw("reservoir", &reservoir)
+        .boolean("silent", frame.silent())
+        .boolean("format_changed", frame.format_changed());
+
+    match scalefactors {
+        Err(ref err) => object.string("scalefactor_error", &err.to_string()).finish(),
+        Ok(_) => object.finish(),
+    }
+}
+
+// Scalefactors need the frame's main data, which silent frames don't have.
+fn read_scalefactors(frame: &Frame) -> Result<[[Scalefactors; 2]; 2], MpError> {
+    if frame.silent() {
+        return Err(ErrorKind::ReservoirUnderflow {
+            needed: frame.side_information().main_data_begin(),
+            available: frame.reservoir(),
+        }.into());
+    }
+
+    scalefactors::read(frame.header(), frame.side_information(), frame.main_data())
+}
+
+fn header_json(header: &Header) -> String {
+    let object = Object::new()
+        .string("version", version_name(header.version()))
+        .number("layer", layer_number(header.layer()))
+        .number("bitrate", header.bitrate())
+        .number("sampling_rate", header.sampling_rate())
+        .number("padding", header.padding())
+        .number("frame_size", header.frame_size())
+        .boolean("protection", header.protection())
+        .boolean("private", header.private())
+        .boolean("copyright", header.copyright())
+        .boolean("original", header.original())
+        .number("emphasis", header.emphasis())
+        .string("channel", channel_name(header.channel()));
+
+    match *header.channel() {
+        ChannelMode::JointStereo(Extension::Stereo(intensity, ms)) => object
+            .boolean("intensity_stereo", intensity)
+            .boolean("ms_stereo", ms)
+            .finish(),
+        ChannelMode::JointStereo(Extension::Bands(bands)) => object.number("bound", bands).finish(),
+        _ => object.finish(),
+    }
+}
+
+// Parsed contents of the tags `Tags::locate` found, as (name, value) pairs. `None` for a tag
+// that is there but can't be parsed.
+struct TagFields {
+    id3v2: Option<Vec<(String, String)>>,
+    ape: Option<Vec<(String, String)>>,
+    id3v1: Option<Vec<(String, String)>>,
+}
+
+impl TagFields {
+    fn read<R: Read + Seek>(reader: &mut R, tags: &Tags) -> Result<TagFields, MpError> {
+        let mut fields = TagFields { id3v2: None, ape: None, id3v1: None };
+        if let Some(range) = tags.id3v2() {
+            fields.id3v2 = Id3Tag::parse(&read_range(reader, range)?)
+                .map(|tag| tag.frames().iter().map(id3_field).collect());
+        }
+        if let Some(range) = tags.ape() {
+            fields.ape = ApeTag::parse(&read_range(reader, range)?)
+                .map(|tag| tag.items().iter().map(|item| {
+                    let value = match str::from_utf8(item.value()) {
+                        Ok(text) if item.is_text() => text.replace('\0', "; "),
+                        _ => format!("{} bytes", item.value().len()),
+                    };
+                    (String::from(item.key()), value)
+                }).collect());
+        }
+        if let Some(range) = tags.id3v1() {
+            fields.id3v1 = Some(id3v1_fields(&read_range(reader, range)?));
+        }
+        Ok(fields)
+    }
+}
+
+fn read_range<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<Vec<u8>, MpError> {
+    let mut data = vec![0u8; (range.end - range.start) as usize];
+    reader.seek(SeekFrom::Start(range.start))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn id3_field(frame: &Id3Frame) -> (String, String) {
+    let id = String::from_utf8_lossy(&frame.id()).into_owned();
+    let value = match *frame {
+        Id3Frame::Text { ref values, .. } => values.join("; "),
+        Id3Frame::UserText { ref description, ref value } => format!("{}: {}", description, value),
+        Id3Frame::Comment { ref language, ref description, ref text } => {
+            format!("[{}] {}{}{}", String::from_utf8_lossy(language), description, if description.is_empty() { "" } else { ": " }, text)
+        },
+        Id3Frame::Picture { ref mime_type, picture_type, ref description, ref data } => {
+            format!("{}, type {}, {} bytes{}{}", mime_type, picture_type, data.len(), if description.is_empty() { "" } else { ", " }, description)
+        },
+        Id3Frame::Private { ref owner, ref data } => format!("{}, {} bytes", owner, data.len()),
+        Id3Frame::Chapter { ref element_id, start_time, end_time, ref frames, .. } => {
+            let title = frames.iter().map(id3_field).find(|field| field.0 == "TIT2").map(|field| field.1);
+            format!("{} {}-{} ms{}", element_id, start_time, end_time, title.map_or(String::new(), |title| format!(" {}", title)))
+        },
+        Id3Frame::TableOfContents { ref element_id, ref children, .. } => format!("{}: {}", element_id, children.join(", ")),
+        Id3Frame::Raw { ref data, .. } => format!("{} bytes", data.len()),
+        Id3Frame::Encoded { ref data, .. } => format!("{} bytes, compressed or encrypted", data.len()),
+    };
+    (id, value)
+}
+
+// Fields of a 128 byte ID3v1 or v1.1 tag, leaving out empty ones.
+fn id3v1_fields(data: &[u8]) -> Vec<(String, String)> {
+    let text = |range: Range<usize>| -> String {
+        let value: String = data[range].iter().map(|&byte| byte as char).collect(); // ISO-8859-1
+        value.trim_end_matches(['\0', ' ']).to_string()
+    };
+
+    // ID3v1.1 keeps a track number in the last two bytes of the comment.
+    let track = if data[125] == 0 && data[126] != 0 { Some(data[126]) } else { None };
+    let mut fields = vec![
+        ("title", text(3..33)),
+        ("artist", text(33..63)),
+        ("album", text(63..93)),
+        ("year", text(93..97)),
+        ("comment", text(97..if track.is_some() { 125 } else { 127 })),
+    ];
+    if let Some(track) = track {
+        fields.push(("track", track.to_string()));
+    }
+    if data[127] != 0xFF {
+        fields.push(("genre", data[127].to_string()));
+    }
+
+    fields.into_iter()
+        .filter(|field| !field.1.is_empty())
+        .map(|(name, value)| (String::from(name), value))
+        .collect()
+}
+
+fn fields_json(fields: &Option<Vec<(String, String)>>, name: &str) -> String {
+    match *fields {
+        Some(ref fields) => json::array(fields.iter().map(|(key, value)| {
+            Object::new().string(name, key).string("value", value).finish()
+        })),
+        None => String::from("null"),
+    }
+}
+
+fn info_json(path: &str, info: &StreamInfo, fields: &TagFields) -> String {
+    let vbr_header = match info.vbr_header() {
+        Some(VbrHeader::Xing(xing)) => {
+            let object = Object::new()
+                .string("type", if xing.info() { "info" } else { "xing" })
+                .raw("frames", &optional(xing.frames()))
+                .raw("bytes", &optional(xing.bytes()))
+                .raw("quality", &optional(xing.quality()));
+            match xing.lame() {
+                Some(lame) => object.raw("lame", &Object::new()
+                    .string("encoder", &encoder_name(lame.encoder()))
+                    .number("vbr_method", lame.vbr_method())
+                    .number("lowpass", lame.lowpass() as u32 * 100)
+                    .number("encoder_delay", lame.encoder_delay())
+                    .number("padding", lame.padding())
+                    .number("music_length", lame.music_length())
+                    .finish()).finish(),
+                None => object.null("lame").finish(),
+            }
+        },
+        Some(VbrHeader::Vbri(vbri)) => Object::new()
+            .string("type", "vbri")
+            .number("frames", vbri.frames())
+            .number("bytes", vbri.bytes())
+            .number("quality", vbri.quality())
+            .number("delay", vbri.delay())
+            .finish(),
+        None => String::from("null"),
+    };
+
+    let tags = info.tags();
+    let range = |range: Option<std::ops::Range<u64>>| match range {
+        Some(range) => Object::new().number("start", range.start).number("end", range.end).finish(),
+        None => String::from("null"),
+    };
+    let tags_json = Object::new()
+        .raw("id3v2", &range(tags.id3v2()))
+        .raw("id3v2_version", &if tags.id3v2().is_some() { tags.id3v2_version().to_string() } else { String::from("null") })
+        .raw("ape", &range(tags.ape()))
+        .raw("id3v1", &range(tags.id3v1()))
+        .raw("audio", &range(Some(tags.audio())))
+        .raw("id3v2_frames", &fields_json(&fields.id3v2, "id"))
+        .raw("ape_items", &fields_json(&fields.ape, "key"))
+        .raw("id3v1_fields", &fields_json(&fields.id3v1, "name"))
+        .finish();
+
+    Object::new()
+        .string("file", path)
+        .string("version", version_name(info.version()))
+        .number("layer", layer_number(info.layer()))
+        .number("sampling_rate", info.sampling_rate())
+        .string("channel", channel_name(info.channel()))
+        .number("emphasis", info.emphasis())
+        .boolean("copyright", info.copyright())
+        .boolean("original", info.original())
+        .number("min_bitrate", info.min_bitrate())
+        .number("max_bitrate", info.max_bitrate())
+        .string("bitrate_mode", bitrate_mode_name(info.bitrate_mode()))
+        .number("frames", info.frames())
+        .number("duration", info.duration().as_secs_f64())
+        .boolean("duration_exact", info.duration_method().is_exact())
+        .number("first_frame", info.first_frame())
+        .raw("vbr_header", &vbr_header)
+        .raw("tags", &tags_json)
+        .finish()
+}
+
+fn problem_json(problem: &Problem) -> String {
+    let object = Object::new();
+    match *problem {
+        Problem::Junk { offset, length } => object.string("type", "junk").number("offset", offset).number("length", length),
+        Problem::LostSync { offset, length } => object.string("type", "lost_sync").number("offset", offset).number("length", length),
+        Problem::SamplingRateChange { offset, from, to } => object
+            .string("type", "sampling_rate_change").number("offset", offset).number("from", from).number("to", to),
+        Problem::ChannelModeChange { offset, ref from, ref to } => object
+            .string("type", "channel_mode_change").number("offset", offset)
+            .string("from", channel_name(from)).string("to", channel_name(to)),
+        Problem::BadCrc { offset, expected, actual } => object
+            .string("type", "bad_crc").number("offset", offset).number("expected", expected).number("actual", actual),
+        Problem::ReservoirViolation { offset, needed, available } => object
+            .string("type", "reservoir_violation").number("offset", offset).number("needed", needed).number("available", available),
+        Problem::TruncatedFrame { offset } => object.string("type", "truncated_frame").number("offset", offset),
+        Problem::FrameCountMismatch { declared, actual } => object
+            .string("type", "frame_count_mismatch").number("declared", declared).number("actual", actual),
+    }.finish()
+}
+
+fn optional<N: ToString>(value: Option<N>) -> String {
+    value.map_or(String::from("null"), |value| value.to_string())
+}
+
+fn version_name(version: &Version) -> &'static str {
+    match *version {
+        Version::Version1 => "1",
+        Version::Version2 => "2",
+        Version::Version2_5 => "2.5",
+        Version::Reserved => "reserved",
+    }
+}
+
+fn layer_number(layer: &Layer) -> u8 {
+    match *layer {
+        Layer::Layer1 => 1,
+        Layer::Layer2 => 2,
+        Layer::Layer3 => 3,
+        Layer::Reserved => 0,
+    }
+}
+
+fn channel_name(channel: &ChannelMode) -> &'static str {
+    match *channel {
+        ChannelMode::Stereo => "stereo",
+        ChannelMode::JointStereo(_) => "joint stereo",
+        ChannelMode::Dual => "dual channel",
+        ChannelMode::Mono => "mono",
+    }
+}
+
+fn block_type_name(block_type: BlockType) -> &'static str {
+    match block_type {
+        BlockType::Normal => "normal",
+        BlockType::Start => "start",
+        BlockType::Short => "short",
+        BlockType::Stop => "stop",
+    }
+}
+
+fn bitrate_mode_name(bitrate_mode: BitrateMode) -> &'static str {
+    match bitrate_mode {
+        BitrateMode::Constant => "CBR",
+        BitrateMode::Average => "ABR",
+        BitrateMode::Variable => "VBR",
+    }
+}
+
+fn encoder_name(encoder: &[u8; 9]) -> String {
+    String::from_utf8_lossy(encoder).trim_end_matches(['\0', ' ']).to_string()
+}
+
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
@@ -0,0 +1,121 @@
+use ::error::{MpError, ErrorKind};
+
+// Integer types a `BitReader` can read into.
+pub trait FromBits: Sized {
+    fn from_bits(bits: u32) -> Self;
+}
+
+impl FromBits for u8 {
+    fn from_bits(bits: u32) -> u8 {
+        bits as u8
+    }
+}
+
+impl FromBits for u16 {
+    fn from_bits(bits: u32) -> u16 {
+        bits as u16
+    }
+}
+
+impl FromBits for u32 {
+    fn from_bits(bits: u32) -> u32 {
+        bits
+    }
+}
+
+// Big endian (most significant bit first) reader over a byte slice, which is how every field
+// in the header, side information and main data is laid out.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize, // Bit position from the start of `data`.
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0,
+        }
+    }
+
+    // Reads `bits` (at most 32) bits into an integer.
+    pub fn read<T: FromBits>(&mut self, bits: u32) -> Result<T, MpError> {
+        debug_assert!(bits <= 32);
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        let mut value: u32 = 0;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+
+        Ok(T::from_bits(value))
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, MpError> {
+        Ok(self.read::<u8>(1)? == 1)
+    }
+
+    pub fn skip(&mut self, bits: u32) -> Result<(), MpError> {
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        self.position += bits as usize;
+        Ok(())
+    }
+
+    // Number of bits read so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+// Big endian writer over a byte slice, the counterpart of `BitReader`. Bits that aren't written
+// keep whatever the slice held.
+pub struct BitWriter<'a> {
+    data: &'a mut [u8],
+    position: usize, // Bit position from the start of `data`.
+}
+
+impl<'a> BitWriter<'a> {
+    pub fn new(data: &'a mut [u8]) -> BitWriter<'a> {
+        BitWriter {
+            data,
+            position: 0,
+        }
+    }
+
+    // Writes the lowest `bits` (at most 32) bits of `value`.
+    pub fn write(&mut self, bits: u32, value: u32) -> Result<(), MpError> {
+        debug_assert!(bits <= 32);
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        for shift in (0..bits).rev() {
+            let bit = ((value >> shift) & 1) as u8;
+            let mask = 0x80 >> (self.position % 8);
+            let byte = &mut self.data[self.position / 8];
+            *byte = if bit == 1 { *byte | mask } else { *byte & !mask };
+            self.position += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> Result<(), MpError> {
+        self.write(1, bit as u32)
+    }
+
+    // Number of bits written so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
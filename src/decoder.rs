@@ -1,95 +1,657 @@
 
-use std::io;
-use std::collections::VecDeque;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::fmt;
 
-use ::error::MpError;
-use ::header::{Header, ChannelMode};
-use ::side_info::SideInformation;
+#[cfg(feature = "alloc")]
+use ::diagnostics::Diagnostics;
+use ::diagnostics::Event;
+use ::error::{MpError, ErrorKind, Location};
+use ::header::{self, Header, ChannelMode, Layer, CHECKSUM_SIZE, HEADER_SIZE};
+use ::side_info::{self, SideInformation};
+use ::source::ByteSource;
 
-use byteorder::ReadBytesExt;
+// Channel selection for dual channel (bilingual) streams, where both channels are independent
+// programs such as two different languages. Has no effect on stereo, joint stereo or mono streams.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum DualChannel {
+    #[default]
+    Both, // Output both channels as a stereo pair
+    First, // Output only channel 0
+    Second, // Output only channel 1
+    Downmix, // Output the average of both channels as mono
+}
+
+impl DualChannel {
+    // Whether channel `ch` has to be decoded for this selection.
+    pub fn decodes(&self, ch: usize) -> bool {
+        match *self {
+            DualChannel::First => ch == 0,
+            DualChannel::Second => ch == 1,
+            DualChannel::Both | DualChannel::Downmix => ch < 2,
+        }
+    }
+
+    // Number of channels this selection outputs.
+    pub fn output_channels(&self) -> usize {
+        match *self {
+            DualChannel::Both => 2,
+            _ => 1,
+        }
+    }
+}
 
-pub struct FrameReader<R: io::Read + io::Seek> {
+// TODO: Decoding options that wait on frames decoding to PCM, which needs Huffman decoding,
+// requantization, stereo processing, IMDCT and synthesis first:
+// - A mono output mode that downmixes after stereo processing, so only one IMDCT and one
+//   synthesis filterbank run per granule.
+// - Half and quarter rate output through a 16 or 8 subband synthesis filterbank, skipping the
+//   spectral processing above the kept bands.
+// - A fixed-point path behind a cargo feature for targets without an FPU, with i16 output
+//   within the ISO 11172-4 limited accuracy bounds.
+// - SIMD IMDCT and synthesis loops picked by runtime feature detection, with the scalar loops
+//   as fallback and tests comparing both on examples/whatislove.mp3.
+// - Concealment of frames with a bad CRC or overrunning main data: silence, the previous
+//   granule's spectrum attenuated, or a fade, keeping the IMDCT and synthesis state consistent.
+pub struct FrameReader<R: ByteSource> {
     // File (preferably buffered), stream, array, etc.
     reader: R,
 
+    // Which channels to decode when the stream is dual channel.
+    dual_channel: DualChannel,
+
+    // Optional receiver for sync, CRC and reservoir problems.
+    #[cfg(feature = "alloc")]
+    diagnostics: Option<Box<dyn Diagnostics>>,
+
+    // Absolute byte offset of the reader.
+    offset: u64,
+
+    // Number of frames read so far.
+    frame: u64,
+
+    // Whether the previous frame was read successfully, so the next header is expected to
+    // follow it immediately.
+    synced: bool,
+
     // Main data bit reservoir for future frames. If we start in the middle and require
-    // main data from prior frames, then we should either error or load the previous frames
-    // if possible.
-    main_data: VecDeque<u8>,
+    // main data from prior frames, then `reservoir_policy` decides between loading the previous
+    // frames, silence or an error. Only the last `MAX_MAIN_DATA_BEGIN` bytes are kept.
+    main_data: MainData,
+
+    reservoir_policy: ReservoirPolicy,
+
+    // Whether CRC mismatches are returned as errors rather than only reported to diagnostics.
+    strict_crc: bool,
+
+    // Number of frames whose main data could not be recovered.
+    underflows: u64,
+
+    // Format of the last Layer III frame, to detect sampling rate and channel mode changes.
+    format: Option<Format>,
+}
+
+// The parts of a stream's format that the decoded output depends on. Concatenated files and
+// some radio feeds change these mid-stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Format {
+    sampling_rate: u16, // Hz
+    channel: ChannelMode,
+}
+
+impl Format {
+    pub fn new(header: &Header) -> Format {
+        Format {
+            sampling_rate: header.sampling_rate(),
+            channel: header.channel().clone(),
+        }
+    }
+
+    #[inline]
+    pub fn sampling_rate(&self) -> u16 {
+        self.sampling_rate
+    }
+
+    #[inline]
+    pub fn channel(&self) -> &ChannelMode {
+        &self.channel
+    }
+
+    // Whether a frame with this header continues the format, ignoring stereo/joint stereo switches.
+    pub fn matches(&self, header: &Header) -> bool {
+        self.sampling_rate == header.sampling_rate() && self.channel.same_layout(header.channel())
+    }
+}
+
+// What to do with a frame whose `main_data_begin` reaches back before the main data we have,
+// which happens when starting in the middle of a stream or after losing sync.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ReservoirPolicy {
+    // Seek back and read the frames before this one to rebuild the reservoir, falling back to
+    // `Silence` if the source can't seek or no previous frames are found.
+    #[default]
+    Backtrack,
+    // Mark the frame as silent and carry on.
+    Silence,
+    // Return `ErrorKind::ReservoirUnderflow` for the frame. Reading can continue afterwards.
+    Error,
+}
+
+// A Layer III frame as read from the stream. Samples are not decoded yet.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    header: Header,
+    side_information: SideInformation,
+    offset: u64, // Absolute byte offset of the header
+    number: u64, // Number of frames read before this one
+    reservoir: usize, // Main data bytes available from previous frames
+    silent: bool, // Main data is missing, the frame decodes to silence
+    crc_mismatch: Option<(u16, u16)>, // Stored and computed CRC of a protected frame that failed the check
+    main_data: MainData, // From `main_data_begin` bytes back to the end of this frame, empty when silent
+    format_changed: bool, // First frame, or the sampling rate or channel layout differs from the previous one
+}
+
+impl Frame {
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    #[inline]
+    pub fn side_information(&self) -> &SideInformation {
+        &self.side_information
+    }
+
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
 
-    // Frames can only reach back 2 consecutive frames into the past. Therefore we only save the
-    // past 2 frames and the current frame we are on.
-    frame_indices: [usize; 3],
+    #[inline]
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    #[inline]
+    pub fn reservoir(&self) -> usize {
+        self.reservoir
+    }
+
+    #[inline]
+    pub fn silent(&self) -> bool {
+        self.silent
+    }
+
+    // The frame's main data (scalefactors and Huffman data of both granules), starting in the
+    // reservoir of previous frames and running to the end of this one. Bits past
+    // `SideInformation::main_data_bits` are reservoir for the frames after it.
+    #[inline]
+    pub fn main_data(&self) -> &[u8] {
+        self.main_data.as_slice()
+    }
+
+    // Whether the output format starts with this frame, so resamplers and sinks should be
+    // (re)configured from its header.
+    #[inline]
+    pub fn format_changed(&self) -> bool {
+        self.format_changed
+    }
 }
 
 // How many bytes ahead should we check before erroring on header seeking.
 const HEADER_LIMIT: usize = 1024 * 10; // 10kB
 
-impl<R: io::Read + io::Seek> FrameReader<R> {
-    pub fn new(reader: R) -> FrameReader<R> {
+// How far back to look for previous frames when rebuilding the reservoir. The reservoir is at
+// most 511 bytes, which even the smallest frames cover within 4kB.
+const BACKTRACK_LIMIT: u64 = 1024 * 4; // 4kB
+
+// Most main data a frame can use: everything `main_data_begin` reaches back to plus its own.
+pub const MAX_MAIN_DATA: usize = side_info::MAX_MAIN_DATA_BEGIN + side_info::MAX_FRAME_SIZE as usize;
+
+// Fixed size buffer for main data, so frames can be read without an allocator.
+#[derive(Clone)]
+struct MainData {
+    data: [u8; MAX_MAIN_DATA],
+    len: usize,
+}
+
+impl MainData {
+    fn new() -> MainData {
+        MainData {
+            data: [0u8; MAX_MAIN_DATA],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    // Makes room for `length` bytes at the end and returns them.
+    fn reserve(&mut self, length: usize) -> &mut [u8] {
+        let start = self.len;
+        self.len += length;
+        &mut self.data[start..self.len]
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.reserve(bytes.len()).copy_from_slice(bytes);
+    }
+
+    fn prepend(&mut self, bytes: &[u8]) {
+        self.data.copy_within(..self.len, bytes.len());
+        self.data[..bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    // Drops everything but the last `MAX_MAIN_DATA_BEGIN` bytes, which is all later frames can
+    // reach.
+    fn trim(&mut self) {
+        let drop = self.len.saturating_sub(side_info::MAX_MAIN_DATA_BEGIN);
+        self.data.copy_within(drop..self.len, 0);
+        self.len -= drop;
+    }
+}
+
+impl fmt::Debug for MainData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl<R: ByteSource> FrameReader<R> {
+    pub fn new(mut reader: R) -> FrameReader<R> {
+        let offset = reader.position().unwrap_or(0);
         FrameReader {
-            reader: reader,
-            main_data: VecDeque::new(),
-            frame_indices: [0; 3],
+            reader,
+            dual_channel: DualChannel::default(),
+            #[cfg(feature = "alloc")]
+            diagnostics: None,
+            offset,
+            frame: 0,
+            synced: false,
+            main_data: MainData::new(),
+            reservoir_policy: ReservoirPolicy::default(),
+            strict_crc: false,
+            underflows: 0,
+            format: None,
+        }
+    }
+
+    // Selects which channels of a dual channel stream get decoded and output.
+    pub fn set_dual_channel(&mut self, dual_channel: DualChannel) {
+        self.dual_channel = dual_channel;
+    }
+
+    #[inline]
+    pub fn dual_channel(&self) -> DualChannel {
+        self.dual_channel
+    }
+
+    // Which of the frame's channels need to be requantized and synthesized. Unselected channels
+    // of a dual channel stream still have their side information parsed, but nothing else.
+    pub fn decoded_channels(&self, header: &Header) -> [bool; 2] {
+        match *header.channel() {
+            ChannelMode::Mono => [true, false],
+            ChannelMode::Dual => [self.dual_channel.decodes(0), self.dual_channel.decodes(1)],
+            _ => [true, true],
+        }
+    }
+
+    // Number of channels in the decoded output for a frame with this header.
+    pub fn output_channels(&self, header: &Header) -> usize {
+        match *header.channel() {
+            ChannelMode::Mono => 1,
+            ChannelMode::Dual => self.dual_channel.output_channels(),
+            _ => 2,
+        }
+    }
+
+    // Receives sync losses, skipped bytes, bad headers, CRC mismatches, reservoir underflows and
+    // format changes. Needs the `alloc` feature.
+    #[cfg(feature = "alloc")]
+    pub fn set_diagnostics<D: Diagnostics + 'static>(&mut self, diagnostics: D) {
+        self.diagnostics = Some(Box::new(diagnostics));
+    }
+
+    // Absolute byte offset of the next byte to be read.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    // Number of frames read so far.
+    #[inline]
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn set_reservoir_policy(&mut self, reservoir_policy: ReservoirPolicy) {
+        self.reservoir_policy = reservoir_policy;
+    }
+
+    #[inline]
+    pub fn reservoir_policy(&self) -> ReservoirPolicy {
+        self.reservoir_policy
+    }
+
+    // Returns `ErrorKind::CrcMismatch` for protected frames that fail the CRC check. The frame's
+    // main data still goes into the reservoir, so reading continues in sync with the next frame.
+    pub fn set_strict_crc(&mut self, strict_crc: bool) {
+        self.strict_crc = strict_crc;
+    }
+
+    #[inline]
+    pub fn strict_crc(&self) -> bool {
+        self.strict_crc
+    }
+
+    // Number of frames so far that were silent or errored because their main data was missing.
+    #[inline]
+    pub fn underflows(&self) -> u64 {
+        self.underflows
+    }
+
+    // Format of the last Layer III frame read, if any.
+    #[inline]
+    pub fn format(&self) -> Option<&Format> {
+        self.format.as_ref()
+    }
+
+    pub fn advance(&mut self) -> Result<Frame, MpError> {
+        let search = Location {
+            offset: self.offset,
+            frame: self.frame,
+        };
+        let (header, header_bytes) = match self.find_header(HEADER_LIMIT) {
+            Ok(found) => found,
+            Err(err) => {
+                self.synced = false;
+                return Err(err.at(search));
+            },
+        };
+
+        let location = Location {
+            offset: self.offset - HEADER_SIZE as u64,
+            frame: self.frame,
+        };
+        let layer = *header.layer();
+        let frame = match self.read_frame(header, &header_bytes, location) {
+            Ok(frame) => frame,
+            Err(err) => {
+                self.synced = false;
+                let err = if err.is_eof() { ErrorKind::TruncatedFrame.into() } else { err };
+                return Err(err.at(location));
+            },
+        };
+        self.synced = true;
+        self.frame += 1;
+
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return Err(MpError::from(ErrorKind::UnsupportedLayer(layer)).at(location)),
+        };
+
+        if frame.silent && self.reservoir_policy == ReservoirPolicy::Error {
+            return Err(MpError::from(ErrorKind::ReservoirUnderflow {
+                needed: frame.side_information.main_data_begin(),
+                available: frame.reservoir,
+            }).at(location));
+        }
+
+        if let (true, Some((expected, actual))) = (self.strict_crc, frame.crc_mismatch) {
+            return Err(MpError::from(ErrorKind::CrcMismatch { expected, actual }).at(location));
+        }
+
+        // TODO: Once granules are decoded, only run requantization and synthesis for the
+        // `decoded_channels` and mix dual channel output according to `dual_channel`.
+        Ok(frame)
+    }
+
+    // Reads the rest of a frame after its header, filling the main data reservoir. Frames of
+    // other layers are skipped over and give `None`.
+    fn read_frame(&mut self, header: Header, header_bytes: &[u8; 4], location: Location) -> Result<Option<Frame>, MpError> {
+        if header.layer() != &Layer::Layer3 {
+            let mut remaining = (header.frame_size() as usize).saturating_sub(HEADER_SIZE);
+            let mut buffer = [0u8; 256];
+            while remaining > 0 {
+                let length = remaining.min(buffer.len());
+                self.read_exact(&mut buffer[..length])?;
+                remaining -= length;
+            }
+            return Ok(None);
+        }
+
+        let (side_information, crc_mismatch) = self.construct_side_information(&header, header_bytes, location.offset)?;
+
+        // `SideInformation::new` limits this to `MAX_FRAME_SIZE`.
+        let mut main_data = MainData::new();
+        self.read_exact(main_data.reserve(side_information.main_data_size() as usize))?;
+
+        // Main data from before a format change belongs to another stream, so a new format starts
+        // with an empty reservoir and doesn't backtrack into the old frames.
+        // TODO: Also reset the IMDCT overlap and synthesis filterbank state here once they exist.
+        let format_changed = match self.format {
+            Some(ref format) if format.matches(&header) => false,
+            Some(ref format) => {
+                let from = format.clone();
+                self.report(Event::FormatChanged {
+                    offset: location.offset,
+                    from,
+                    to: Format::new(&header),
+                });
+                self.main_data.clear();
+                true
+            },
+            None => true,
+        };
+        let switched = format_changed && self.format.is_some();
+        self.format = Some(Format::new(&header));
+
+        let main_data_begin = side_information.main_data_begin() as usize;
+        if main_data_begin > self.main_data.len && self.reservoir_policy == ReservoirPolicy::Backtrack && !switched {
+            let resume = self.offset;
+            if let Some(reservoir) = self.backtrack(location.offset, resume) {
+                self.main_data = reservoir;
+            }
+        }
+
+        let reservoir = self.main_data.len;
+        let silent = main_data_begin > reservoir;
+        if silent {
+            self.underflows += 1;
+            self.report(Event::ReservoirUnderflow {
+                offset: location.offset,
+                needed: main_data_begin as u16,
+                available: reservoir,
+            });
+        }
+
+        if silent {
+            self.fill_reservoir(main_data.as_slice());
+            main_data.clear();
+        } else {
+            main_data.prepend(&self.main_data.as_slice()[reservoir - main_data_begin..]);
+            self.fill_reservoir(&main_data.as_slice()[main_data_begin..]);
         }
+
+        Ok(Some(Frame {
+            header,
+            side_information,
+            offset: location.offset,
+            number: location.frame,
+            reservoir,
+            silent,
+            crc_mismatch,
+            main_data,
+            format_changed,
+        }))
     }
 
-    pub fn advance(&mut self) -> Result<(), MpError> {
-        let header = self.find_header(HEADER_LIMIT).ok_or(MpError::NoHeaderCapture)?;
-        dbg!(header.clone());
+    // Rebuilds the reservoir from the frames right before `offset` by reading back up to
+    // `BACKTRACK_LIMIT` bytes and finding a chain of headers that ends exactly at `offset`. The
+    // reader is left at `resume` either way.
+    fn backtrack(&mut self, offset: u64, resume: u64) -> Option<MainData> {
+        let start = offset.saturating_sub(BACKTRACK_LIMIT);
+        let mut buffer = [0u8; BACKTRACK_LIMIT as usize];
+        let window = &mut buffer[..(offset - start) as usize];
+        let read = self.reader.seek(start).and_then(|_| self.reader.read_exact(window));
+        if self.reader.seek(resume).is_err() || read.is_err() {
+            return None;
+        }
 
-        let side_information = self.construct_side_information(&header)?;
-        dbg!(side_information.clone());
-        Err(MpError::InvalidData("Unimplemented".to_string()))
+        (0..window.len()).filter_map(|candidate| chain_main_data(&window[candidate..])).next()
     }
 
-    // Find the next MP3 header within a limit of bytes.
-    fn find_header(&mut self, limit: usize) -> Option<Header> {
+    // Find the next MP3 header within a limit of bytes, returning it along with its raw bytes.
+    fn find_header(&mut self, limit: usize) -> Result<(Header, [u8; 4]), MpError> {
+        let start = self.offset;
         let mut header_bytes: [u8; 4] = [
-            self.reader.read_u8().ok()?,
-            self.reader.read_u8().ok()?,
-            self.reader.read_u8().ok()?,
-            self.reader.read_u8().ok()?,
+            self.read_u8()?,
+            self.read_u8()?,
+            self.read_u8()?,
+            self.read_u8()?,
         ];
         let mut amount_read = 4;
 
         while amount_read < limit {
-            if header_bytes[0] == 0xFF && header_bytes[1] & 0xE0 == 0xE0 {
+            let header_offset = self.offset - HEADER_SIZE as u64;
+            if header::sync(&header_bytes) {
                 match Header::new(&header_bytes) {
-                    Ok(header) => return Some(header),
-                    Err(err) => {
-                        dbg!(err);
+                    Ok(header) => {
+                        if header_offset > start {
+                            self.report_skipped(start, header_offset);
+                        }
+                        return Ok((header, header_bytes));
                     },
+                    Err(error) => self.report(Event::BadHeader {
+                        offset: header_offset,
+                        error,
+                    }),
                 }
             }
 
             header_bytes[0] = header_bytes[1];
             header_bytes[1] = header_bytes[2];
             header_bytes[2] = header_bytes[3];
-            header_bytes[3] = self.reader.read_u8().ok()?;
+            header_bytes[3] = match self.read_u8() {
+                Ok(byte) => byte,
+                Err(err) => {
+                    self.report_skipped(start, self.offset);
+                    return Err(err);
+                },
+            };
             amount_read += 1;
         }
 
-        None
+        self.report_skipped(start, self.offset);
+        Err(ErrorKind::SyncLost.into())
     }
 
-    fn construct_side_information(&mut self, header: &Header) -> Result<SideInformation, MpError> {
-        let mut mono_buffer = [0u8; 17];
-        let mut dual_buffer = [0u8; 32];
-        let side_info_data: &[u8] = if header.channel() == &ChannelMode::Mono {
-            self.reader.read_exact(&mut mono_buffer)?;
-            &mono_buffer
-        } else {
-            self.reader.read_exact(&mut dual_buffer)?;
-            &dual_buffer
-        };
+    // Reads and parses the side information, along with the stored and computed CRC if they differ.
+    fn construct_side_information(&mut self, header: &Header, header_bytes: &[u8; 4], offset: u64) -> Result<(SideInformation, Option<(u16, u16)>), MpError> {
+        let mut checksum = [0u8; 2];
+        if header.protection() {
+            self.read_exact(&mut checksum)?;
+        }
+
+        let mut buffer = [0u8; 32];
+        let side_info_data = &mut buffer[..side_info::size(header)];
+        self.read_exact(side_info_data)?;
+        let side_info_data: &[u8] = side_info_data;
+
+        let mut crc_mismatch = None;
+        if header.protection() {
+            let expected = u16::from_be_bytes(checksum);
+            let actual = side_info::crc(&header_bytes[2..], side_info_data);
+            if expected != actual {
+                self.report(Event::CrcMismatch {
+                    offset,
+                    expected,
+                    actual,
+                });
+                crc_mismatch = Some((expected, actual));
+            }
+        }
+
+        Ok((SideInformation::new(header, side_info_data)?, crc_mismatch))
+    }
+
+    // Appends a frame's main data to the reservoir, only keeping what later frames can reach.
+    fn fill_reservoir(&mut self, main_data: &[u8]) {
+        self.main_data.extend(main_data);
+        self.main_data.trim();
+    }
+
+    // Diagnostics need `alloc`, without it events are dropped.
+    fn report(&mut self, event: Event) {
+        #[cfg(feature = "alloc")]
+        {
+            if let Some(ref mut diagnostics) = self.diagnostics {
+                diagnostics.report(&event);
+            }
+        }
+        #[cfg(not(feature = "alloc"))]
+        let _ = event;
+    }
+
+    // Reports the bytes in `start..end` that were skipped looking for a header.
+    fn report_skipped(&mut self, start: u64, end: u64) {
+        if self.synced {
+            self.report(Event::SyncLost { offset: start });
+        }
+
+        if end > start {
+            self.report(Event::Skipped {
+                offset: start,
+                length: end - start,
+            });
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, MpError> {
+        let byte = self.reader.read_u8()?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MpError> {
+        self.reader.read_exact(buf)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+}
 
-        print!("side_info_data: ");
-        for byte in side_info_data {
-            print!("{:08b} ", byte);
+// Collects the main data of the back to back Layer III frames filling all of `data`, or `None`
+// if `data` isn't exactly such a run of frames.
+fn chain_main_data(data: &[u8]) -> Option<MainData> {
+    let mut main_data = MainData::new();
+    let mut position = 0;
+    while position < data.len() {
+        let header = Header::new(data.get(position..position + HEADER_SIZE)?).ok()?;
+        let frame_size = header.frame_size() as usize;
+        if header.layer() != &Layer::Layer3 || frame_size == 0 {
+            return None;
         }
-        println!();
 
-        Ok(SideInformation::new(&header, &side_info_data)?)
+        let frame = data.get(position..position + frame_size)?;
+        let mut side_info_start = HEADER_SIZE;
+        if header.protection() {
+            side_info_start += CHECKSUM_SIZE;
+        }
+        let side_info_end = side_info_start + side_info::size(&header);
+        let side_information = SideInformation::new(&header, frame.get(side_info_start..side_info_end)?).ok()?;
+        let main_data_end = side_info_end + side_information.main_data_size() as usize;
+
+        main_data.extend(frame.get(side_info_end..main_data_end)?);
+        main_data.trim();
+        position += frame_size;
     }
+
+    Some(main_data)
 }
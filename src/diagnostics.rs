@@ -0,0 +1,31 @@
+use ::decoder::Format;
+use ::error::MpError;
+
+// Problems found while reading a stream that the frame reader recovers from on its own.
+// Offsets are absolute byte offsets into the source.
+#[derive(Debug)]
+pub enum Event {
+    // The bytes following the previous frame were not a header.
+    SyncLost { offset: u64 },
+    // Bytes passed over while searching for the next header (tags, junk, broken frames).
+    Skipped { offset: u64, length: u64 },
+    // Found a frame sync but the header behind it did not parse.
+    BadHeader { offset: u64, error: MpError },
+    // A protected frame's stored CRC does not match its header and side information.
+    CrcMismatch { offset: u64, expected: u16, actual: u16 },
+    // A frame's `main_data_begin` reaches further back than the main data read so far.
+    ReservoirUnderflow { offset: u64, needed: u16, available: usize },
+    // The sampling rate or channel layout changed, the reservoir was reset for the new format.
+    FormatChanged { offset: u64, from: Format, to: Format },
+}
+
+// Opt-in receiver for `Event`s, see `FrameReader::set_diagnostics`. Implemented for closures.
+pub trait Diagnostics {
+    fn report(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event)> Diagnostics for F {
+    fn report(&mut self, event: &Event) {
+        self(event)
+    }
+}
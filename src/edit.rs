@@ -0,0 +1,583 @@
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::slice;
+use std::time::Duration;
+use std::vec::Vec;
+
+use crc16::{State, ARC};
+
+use ::decoder::{FrameReader, ReservoirPolicy};
+use ::error::{MpError, ErrorKind, Mismatch};
+use ::header::{Header, CHECKSUM_SIZE, HEADER_SIZE};
+use ::probe::{self, Scan};
+use ::side_info::{self, SideInformation};
+use ::source::{Bounded, ByteSource, SetLen};
+use ::vbr::{self, BitrateMode, LameHeader, VbrHeader, XingHeader};
+
+// What to do with frames at the start of an edit whose `main_data_begin` reaches into frames
+// that were cut away.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ReservoirFix {
+    // Put the reservoir bytes they need into silent frames in front of them, and add those frames
+    // to the encoder delay so gapless players skip them.
+    #[default]
+    CarryOver,
+    // Replace them with silent frames. Their main data areas are kept for the frames after them.
+    Silence,
+}
+
+// What an edit wrote.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EditSummary {
+    frames: u64, // Audio frames, not counting the Xing frame
+    bytes: u64, // Everything written, the Xing frame included
+    carrier_frames: u64, // Silent frames added to carry reservoir bytes
+    silenced_frames: u64, // Frames replaced by silence
+    encoder_delay: u16, // Samples gapless players skip at the start
+    padding: u16, // Samples gapless players skip at the end
+}
+
+impl EditSummary {
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    #[inline]
+    pub fn carrier_frames(&self) -> u64 {
+        self.carrier_frames
+    }
+
+    #[inline]
+    pub fn silenced_frames(&self) -> u64 {
+        self.silenced_frames
+    }
+
+    #[inline]
+    pub fn encoder_delay(&self) -> u16 {
+        self.encoder_delay
+    }
+
+    #[inline]
+    pub fn padding(&self) -> u16 {
+        self.padding
+    }
+}
+
+// Cuts frame ranges out of a stream without decoding it, or joins it with others in `concat`.
+// Frames are numbered from the first audio frame, a VBR header frame isn't counted. The output is
+// audio only, tags aren't copied.
+pub struct Editor<R> {
+    reader: R,
+    audio: Range<u64>, // Between the tags
+    frames: Vec<Entry>,
+    lame: Option<LameHeader>, // From the input's Xing header, reused for the output
+    reservoir_fix: ReservoirFix,
+}
+
+// An input frame's position and everything in front of its main data.
+struct Entry {
+    offset: u64,
+    header: Header,
+    side_information: SideInformation,
+}
+
+// Output frames for part of an input.
+struct Plan {
+    pieces: Vec<Piece>,
+    carrier_frames: u64,
+    silenced_frames: u64,
+}
+
+// One frame of the output.
+enum Piece {
+    Copy(usize), // Input frame, unchanged
+    Silence(usize), // Input frame with silent side information, main data area kept
+    Carrier(Header, Vec<u8>), // Silent frame, holding reservoir bytes at the end of its main data area
+}
+
+impl Entry {
+    // Bytes in front of the main data area.
+    fn head_size(&self) -> usize {
+        head_size(&self.header)
+    }
+}
+
+impl<R: ByteSource> Editor<R> {
+    // Reads every frame header and side information of the stream.
+    pub fn new(mut reader: R) -> Result<Editor<R>, MpError> {
+        let info = probe::probe(&mut reader, Scan::Bitrate)?;
+        let audio = info.tags().audio();
+        let (skip, lame) = match info.vbr_header() {
+            Some(VbrHeader::Xing(xing)) => (Some(info.first_frame()), xing.lame().copied()),
+            Some(VbrHeader::Vbri(_)) => (Some(info.first_frame()), None),
+            None => (None, None),
+        };
+
+        let mut frames = Vec::new();
+        reader.seek(audio.start)?;
+        {
+            let mut frame_reader = FrameReader::new(Bounded::new(&mut reader, audio.end)?);
+            // The editor works out reservoir use itself, reading back would only cost time.
+            frame_reader.set_reservoir_policy(ReservoirPolicy::Silence);
+            loop {
+                let frame = match frame_reader.advance() {
+                    Ok(frame) => frame,
+                    Err(ref err) if err.is_recoverable() => continue,
+                    Err(ref err) if err.is_eof() => break,
+                    Err(ref err) if matches!(err.kind(), ErrorKind::TruncatedFrame) => break,
+                    Err(err) => return Err(err),
+                };
+                if Some(frame.offset()) == skip {
+                    continue;
+                }
+
+                frames.push(Entry {
+                    offset: frame.offset(),
+                    header: frame.header().clone(),
+                    side_information: frame.side_information().clone(),
+                });
+            }
+        }
+
+        // Xing frames left in the middle of the stream, by naive concatenation for example. They
+        // are silent and don't use the reservoir, so they can simply be dropped.
+        let mut tag = [0u8; 4];
+        let mut index = 0;
+        while index < frames.len() {
+            let entry = &frames[index];
+            if entry.side_information.main_data_bits() == 0 && entry.side_information.main_data_begin() == 0 {
+                reader.seek(entry.offset + vbr::xing_offset(&entry.header) as u64)?;
+                reader.read_exact(&mut tag)?;
+                if &tag == b"Xing" || &tag == b"Info" {
+                    frames.remove(index);
+                    continue;
+                }
+            }
+            index += 1;
+        }
+
+        if frames.is_empty() {
+            return Err(ErrorKind::SyncLost.into());
+        }
+
+        Ok(Editor {
+            reader,
+            audio,
+            frames,
+            lame,
+            reservoir_fix: ReservoirFix::default(),
+        })
+    }
+
+    pub fn set_reservoir_fix(&mut self, reservoir_fix: ReservoirFix) {
+        self.reservoir_fix = reservoir_fix;
+    }
+
+    #[inline]
+    pub fn reservoir_fix(&self) -> ReservoirFix {
+        self.reservoir_fix
+    }
+
+    // Number of audio frames in the stream.
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    // The frame playing at `time`, counting from the first frame (the encoder delay included).
+    pub fn frame_at(&self, time: Duration) -> u64 {
+        let header = &self.frames[0].header;
+        let samples = time.as_secs_f64() * header.sampling_rate() as f64;
+        (samples / header.samples_per_frame() as f64) as u64
+    }
+
+    // Writes frames `range` as a stream of their own, with a Xing/LAME header in front holding the
+    // new frame count, seek table, and encoder delay and padding for gapless playback.
+    pub fn extract<W: Write + Seek>(&mut self, range: Range<u64>, output: &mut W) -> Result<EditSummary, MpError> {
+        let frames = self.frames.len() as u64;
+        if range.start >= range.end || range.end > frames {
+            return Err(ErrorKind::BadFrameRange {
+                start: range.start,
+                end: range.end,
+                frames,
+            }.into());
+        }
+        let (start, end) = (range.start as usize, range.end as usize);
+
+        let plan = self.plan(start, end)?;
+
+        // Gapless information, relative to the samples of the original frames.
+        let samples_per_frame = self.frames[0].header.samples_per_frame() as u64;
+        let (delay, padding) = match self.lame {
+            Some(ref lame) => (lame.encoder_delay() as u64, lame.padding() as u64),
+            None => (0, 0),
+        };
+        let first_sample = range.start * samples_per_frame;
+        let last_sample = frames * samples_per_frame - padding.min(frames * samples_per_frame);
+        let encoder_delay = plan.carrier_frames * samples_per_frame + delay.saturating_sub(first_sample);
+        let padding = (range.end * samples_per_frame).saturating_sub(last_sample);
+
+        let whole = range.start == 0 && range.end == frames;
+        write_stream(slice::from_mut(self), &[plan], Some((encoder_delay, padding)), whole, output)
+    }
+
+    // Splits the stream at the frames in `points` (ascending), writing each part to the output
+    // `outputs` gives for its index.
+    pub fn split<W, F>(&mut self, points: &[u64], mut outputs: F) -> Result<Vec<EditSummary>, MpError>
+        where W: Write + Seek, F: FnMut(usize) -> Result<W, MpError>
+    {
+        let mut bounds = Vec::with_capacity(points.len() + 2);
+        bounds.push(0);
+        bounds.extend_from_slice(points);
+        bounds.push(self.frames());
+
+        let mut summaries = Vec::with_capacity(bounds.len() - 1);
+        for (index, part) in bounds.windows(2).enumerate() {
+            let mut output = outputs(index)?;
+            summaries.push(self.extract(part[0]..part[1], &mut output)?);
+        }
+        Ok(summaries)
+    }
+
+    // Output frames for input frames `start..end`, with the reservoir fixed up at the start.
+    fn plan(&mut self, start: usize, end: usize) -> Result<Plan, MpError> {
+        let mut plan = Plan {
+            pieces: Vec::with_capacity(end - start),
+            carrier_frames: 0,
+            silenced_frames: 0,
+        };
+
+        let reach = self.reach(start, end);
+        // There is nothing to carry over at the start of the stream.
+        let reservoir_fix = if start == 0 { ReservoirFix::Silence } else { self.reservoir_fix };
+        match reservoir_fix {
+            ReservoirFix::CarryOver if reach > 0 => {
+                let carriers = self.carriers(start, reach)?;
+                plan.carrier_frames = carriers.len() as u64;
+                plan.pieces.extend(carriers);
+                plan.pieces.extend((start..end).map(Piece::Copy));
+            },
+            ReservoirFix::Silence if reach > 0 => {
+                let mut position = 0;
+                for index in start..end {
+                    let entry = &self.frames[index];
+                    if entry.side_information.main_data_begin() as usize > position {
+                        plan.silenced_frames += 1;
+                        plan.pieces.push(Piece::Silence(index));
+                    } else {
+                        plan.pieces.push(Piece::Copy(index));
+                    }
+                    position += entry.side_information.main_data_size() as usize;
+                }
+            },
+            _ => plan.pieces.extend((start..end).map(Piece::Copy)),
+        }
+        Ok(plan)
+    }
+
+    // How many bytes from before frame `start`'s main data area the frames `start..end` need.
+    fn reach(&self, start: usize, end: usize) -> usize {
+        let mut reach = 0;
+        let mut position = 0;
+        for entry in &self.frames[start..end] {
+            let begin = entry.side_information.main_data_begin() as usize;
+            reach = reach.max(begin.saturating_sub(position));
+            position += entry.side_information.main_data_size() as usize;
+            if position >= side_info::MAX_MAIN_DATA_BEGIN {
+                break;
+            }
+        }
+        reach
+    }
+
+    // Silent frames in the format of frame `start` carrying the `reach` bytes of main data in front
+    // of it. Bytes from before the start of the stream (a broken stream to begin with) are 0.
+    fn carriers(&mut self, start: usize, reach: usize) -> Result<Vec<Piece>, MpError> {
+        let mut reservoir = vec![0u8; reach];
+        let mut missing = reach;
+        let mut index = start;
+        while missing > 0 && index > 0 {
+            index -= 1;
+            let frame = self.read_frame(index)?;
+            let main_data = &frame[self.frames[index].head_size()..];
+            let length = main_data.len().min(missing);
+            reservoir[missing - length..missing].copy_from_slice(&main_data[main_data.len() - length..]);
+            missing -= length;
+        }
+
+        let header = self.frames[start].header.clone();
+        let capacity = SideInformation::silent(&header).main_data_size() as usize;
+        let count = reach.div_ceil(capacity);
+        let mut area = vec![0u8; count * capacity];
+        let length = area.len();
+        area[length - reach..].copy_from_slice(&reservoir);
+
+        Ok(area.chunks(capacity).map(|main_data| Piece::Carrier(header.clone(), main_data.to_vec())).collect())
+    }
+
+    fn read_frame(&mut self, index: usize) -> Result<Vec<u8>, MpError> {
+        let entry = &self.frames[index];
+        let mut frame = vec![0u8; entry.header.frame_size() as usize];
+        self.reader.seek(entry.offset)?;
+        self.reader.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+
+    fn piece_header(&self, piece: &Piece) -> Header {
+        match *piece {
+            Piece::Copy(index) | Piece::Silence(index) => self.frames[index].header.clone(),
+            Piece::Carrier(ref header, _) => header.clone(),
+        }
+    }
+
+    fn piece_bytes(&mut self, piece: &Piece) -> Result<Vec<u8>, MpError> {
+        match *piece {
+            Piece::Copy(index) => self.read_frame(index),
+            Piece::Silence(index) => {
+                let mut frame = self.read_frame(index)?;
+                let header = &self.frames[index].header;
+                side_info::write_frame_head(header, &SideInformation::silent(header), &mut frame)?;
+                Ok(frame)
+            },
+            Piece::Carrier(ref header, ref main_data) => {
+                let mut frame = vec![0u8; header.frame_size() as usize];
+                let head = side_info::write_frame_head(header, &SideInformation::silent(header), &mut frame)?;
+                frame[head..head + main_data.len()].copy_from_slice(main_data);
+                Ok(frame)
+            },
+        }
+    }
+
+}
+
+// Joins whole streams into one, with a single Xing/LAME header holding the totals. The encoder
+// delay comes from the first input and the padding from the last, the gaps of the inputs in
+// between stay audible. Inputs that can't be joined are reported as `ErrorKind::Incompatible`
+// for the first mismatching frame, see `check_compatible` for all of them.
+pub fn concat<R: ByteSource, W: Write + Seek>(inputs: &mut [Editor<R>], output: &mut W) -> Result<EditSummary, MpError> {
+    if inputs.is_empty() {
+        return Err(ErrorKind::SyncLost.into());
+    }
+    if let Some((input, frame, mismatch)) = check_compatible(inputs).into_iter().next() {
+        return Err(ErrorKind::Incompatible { input, frame, mismatch }.into());
+    }
+
+    let mut plans = Vec::with_capacity(inputs.len());
+    for input in inputs.iter_mut() {
+        let frames = input.frames.len();
+        plans.push(input.plan(0, frames)?);
+    }
+
+    let (first, last) = (&inputs[0], &inputs[inputs.len() - 1]);
+    let samples_per_frame = first.frames[0].header.samples_per_frame() as u64;
+    let encoder_delay = plans[0].carrier_frames * samples_per_frame + first.lame.map_or(0, |lame| lame.encoder_delay() as u64);
+    let padding = last.lame.map_or(0, |lame| lame.padding() as u64);
+    let whole = inputs.len() == 1;
+    write_stream(inputs, &plans, Some((encoder_delay, padding)), whole, output)
+}
+
+// Rebuilds the Xing header of a file from its frames, for when it is missing or wrong. CBR
+// streams get an Info header, and Xing frames inside the stream are dropped. With `keep_gapless`
+// an existing LAME header is kept with its encoder delay and padding, otherwise none is written.
+// Tags are kept, the new audio is put together in memory before the file is rewritten. Frames
+// that change format can't be described by one header, the first is reported as
+// `ErrorKind::Incompatible` (with input 0) and the file is left alone.
+pub fn repair_vbr_header<F: Read + Write + Seek + SetLen>(file: &mut F, keep_gapless: bool) -> Result<EditSummary, MpError> {
+    let mut buffer = Cursor::new(Vec::new());
+    let (audio, summary) = {
+        let mut editor = Editor::new(&mut *file)?;
+        if let Some((input, frame, mismatch)) = check_compatible(slice::from_ref(&editor)).into_iter().next() {
+            return Err(ErrorKind::Incompatible { input, frame, mismatch }.into());
+        }
+        let frames = editor.frames.len();
+        let plan = editor.plan(0, frames)?;
+        let gapless = match editor.lame {
+            Some(lame) if keep_gapless => Some((lame.encoder_delay() as u64, lame.padding() as u64)),
+            _ => None,
+        };
+        let summary = write_stream(slice::from_mut(&mut editor), &[plan], gapless, true, &mut buffer)?;
+        (editor.audio.clone(), summary)
+    };
+
+    let length = file.seek(SeekFrom::End(0))?;
+    let mut tail = vec![0u8; (length - audio.end) as usize];
+    file.seek(SeekFrom::Start(audio.end))?;
+    file.read_exact(&mut tail)?;
+
+    let buffer = buffer.into_inner();
+    file.seek(SeekFrom::Start(audio.start))?;
+    file.write_all(&buffer)?;
+    file.write_all(&tail)?;
+    file.set_len(audio.start + (buffer.len() + tail.len()) as u64)?;
+    file.flush()?;
+    Ok(summary)
+}
+
+// Every input (by index) whose frames don't match the format of the first input's first frame,
+// with the first such frame and how it differs. Stereo and joint stereo frames mix fine.
+pub fn check_compatible<R>(inputs: &[Editor<R>]) -> Vec<(usize, u64, Mismatch)> {
+    let mut incompatible = Vec::new();
+    let expected = match inputs.first() {
+        Some(first) => &first.frames[0].header,
+        None => return incompatible,
+    };
+
+    for (input, editor) in inputs.iter().enumerate() {
+        let mismatch = editor.frames.iter().enumerate().filter_map(|(frame, entry)| {
+            let header = &entry.header;
+            let mismatch = if header.version() != expected.version() {
+                Mismatch::Version(*expected.version(), *header.version())
+            } else if header.layer() != expected.layer() {
+                Mismatch::Layer(*expected.layer(), *header.layer())
+            } else if header.sampling_rate() != expected.sampling_rate() {
+                Mismatch::SamplingRate(expected.sampling_rate(), header.sampling_rate())
+            } else if !header.channel().same_layout(expected.channel()) {
+                Mismatch::ChannelMode(expected.channel().clone(), header.channel().clone())
+            } else {
+                return None;
+            };
+            Some((input, frame as u64, mismatch))
+        }).next();
+        incompatible.extend(mismatch);
+    }
+    incompatible
+}
+
+// Writes the Xing frame followed by the frames planned for each input. With `gapless` (encoder
+// delay and padding) a LAME header based on the first input's is added. Its VBR method is
+// corrected if the written frames contradict it, and unless the output is `whole` (all of a
+// single input) its peak and ReplayGain are cleared, as they were measured on other audio.
+fn write_stream<R: ByteSource, W: Write + Seek>(inputs: &mut [Editor<R>], plans: &[Plan], gapless: Option<(u64, u64)>, whole: bool, output: &mut W) -> Result<EditSummary, MpError> {
+    let mut headers = Vec::new();
+    for (input, plan) in inputs.iter().zip(plans) {
+        headers.extend(plan.pieces.iter().map(|piece| input.piece_header(piece)));
+    }
+    let bitrate_mode = if headers.iter().all(|header| header.bitrate() == headers[0].bitrate()) {
+        BitrateMode::Constant
+    } else {
+        BitrateMode::Variable
+    };
+    let lame = gapless.map(|(encoder_delay, padding)| {
+        let mut lame = inputs[0].lame.unwrap_or_else(|| LameHeader::new(bitrate_mode, 0, 0));
+        // ABR streams vary their bitrate too, so only a CBR method on VBR frames or the other way
+        // around is replaced.
+        let consistent = match lame.bitrate_mode() {
+            Some(BitrateMode::Constant) => bitrate_mode == BitrateMode::Constant,
+            Some(_) => bitrate_mode != BitrateMode::Constant,
+            None => false,
+        };
+        if !consistent {
+            lame.set_bitrate_mode(bitrate_mode);
+        }
+        if !whole {
+            lame.clear_replay_gain();
+        }
+        lame.set_encoder_delay(encoder_delay.min(vbr::MAX_GAPLESS as u64) as u16);
+        lame.set_padding(padding.min(vbr::MAX_GAPLESS as u64) as u16);
+        lame
+    });
+
+    let mut writer = StreamWriter::new(&headers, bitrate_mode, lame, output)?;
+    for (input, plan) in inputs.iter_mut().zip(plans) {
+        for piece in &plan.pieces {
+            let frame = input.piece_bytes(piece)?;
+            writer.write_frame(&frame)?;
+        }
+    }
+    let (frames, bytes, lame) = writer.finish()?;
+
+    Ok(EditSummary {
+        frames,
+        bytes,
+        carrier_frames: plans.iter().map(|plan| plan.carrier_frames).sum(),
+        silenced_frames: plans.iter().map(|plan| plan.silenced_frames).sum(),
+        encoder_delay: lame.map_or(0, |lame| lame.encoder_delay()),
+        padding: lame.map_or(0, |lame| lame.padding()),
+    })
+}
+
+// Writes a stream of frames behind a Xing frame. The Xing frame is written last, once the music
+// CRC is known, so the output has to be seekable.
+struct StreamWriter<'a, W: 'a> {
+    output: &'a mut W,
+    start: u64, // Output position of the Xing frame
+    xing_header: Header,
+    xing: XingHeader,
+    lame: Option<LameHeader>,
+    frames: u64,
+    bytes: u64, // Including the Xing frame
+    crc: State<ARC>,
+}
+
+impl<'a, W: Write + Seek> StreamWriter<'a, W> {
+    // Reserves space for a Xing frame describing frames with `headers`.
+    fn new(headers: &[Header], bitrate_mode: BitrateMode, lame: Option<LameHeader>, output: &'a mut W) -> Result<StreamWriter<'a, W>, MpError> {
+        let info = bitrate_mode == BitrateMode::Constant;
+        // The Xing frame's size doesn't depend on the counts, only on which fields are present.
+        let mut xing = XingHeader::new(info, 0, 0, [0; vbr::XING_TOC_SIZE]);
+        xing.set_lame(lame);
+        let xing_header = xing.frame_header(&headers[0])?;
+        let xing_size = xing_header.frame_size() as u64;
+
+        let mut positions = Vec::with_capacity(headers.len());
+        let mut bytes = xing_size;
+        for header in headers {
+            positions.push(bytes);
+            bytes += header.frame_size() as u64;
+        }
+        let mut xing = XingHeader::new(info, headers.len() as u32, bytes as u32, vbr::toc(&positions, bytes));
+        xing.set_lame(lame);
+
+        let start = output.stream_position()?;
+        output.write_all(&vec![0u8; xing_size as usize])?;
+        Ok(StreamWriter {
+            output,
+            start,
+            xing_header,
+            xing,
+            lame,
+            frames: 0,
+            bytes: xing_size,
+            crc: State::<ARC>::new(),
+        })
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<(), MpError> {
+        self.output.write_all(frame)?;
+        self.crc.update(frame);
+        self.frames += 1;
+        self.bytes += frame.len() as u64;
+        Ok(())
+    }
+
+    // Writes the Xing frame and leaves the output at the end of the stream.
+    fn finish(mut self) -> Result<(u64, u64, Option<LameHeader>), MpError> {
+        if let Some(ref mut lame) = self.lame {
+            lame.set_music(self.bytes as u32, self.crc.get());
+        }
+        self.xing.set_lame(self.lame);
+
+        let mut frame = vec![0u8; self.xing_header.frame_size() as usize];
+        self.xing.write(&self.xing_header, &mut frame)?;
+        self.output.seek(SeekFrom::Start(self.start))?;
+        self.output.write_all(&frame)?;
+        self.output.seek(SeekFrom::Start(self.start + self.bytes))?;
+        self.output.flush()?;
+        Ok((self.frames, self.bytes, self.lame))
+    }
+}
+
+// Bytes in front of a frame's main data area: header, CRC and side information.
+fn head_size(header: &Header) -> usize {
+    let mut size = HEADER_SIZE + side_info::size(header);
+    if header.protection() {
+        size += CHECKSUM_SIZE;
+    }
+    size
+}
@@ -1,25 +1,220 @@
 
+use core::fmt::{self, Display, Formatter};
+
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
 use std::io;
 
-use ::header::{Layer, ChannelMode};
+use ::header::{Layer, ChannelMode, Version};
+
+#[derive(Debug)]
+pub struct MpError {
+    kind: ErrorKind,
+    location: Option<Location>, // Only known for errors coming out of the frame reader.
+}
+
+// Where in the stream an error happened.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Location {
+    pub offset: u64, // Absolute byte offset of the frame, or of the header search that failed
+    pub frame: u64, // Number of frames read before this one
+}
 
 #[derive(Debug)]
-pub enum MpError {
+pub enum ErrorKind {
+    #[cfg(feature = "std")]
     IOError(io::Error),
-    EOF,
-    InvalidData(String),
-    NoHeaderCapture, // could not capture header sync
-    BadBit(u16), // bit index was non-existent or forbidden
-    InvalidMode(ChannelMode, Vec<ChannelMode>), // got, expected (on of)
-    Reserved, // input a reserved mode, version, or layer
+    EOF, // source ran out of data
+    NotSeekable, // source can only be read front to back
+    SyncLost, // could not capture header sync within the search limit
+    Reserved(Field), // input a reserved mode, version, or layer
+    BadBitrate(u8), // bitrate index was forbidden
+    BadBitrateForMode {
+        bitrate: u16,
+        channel: ChannelMode, // got
+        allowed: &'static [ChannelMode], // expected (one of)
+    },
+    BadHeaderLength(usize),
+    FrameTooLarge(u16),
+    FreeFormat, // free format bitrate, frame sizes can't be derived from the headers
+    TruncatedFrame, // source ended in the middle of a frame
+    CrcMismatch { expected: u16, actual: u16 }, // only with `FrameReader::set_strict_crc`
+    ReservoirUnderflow { needed: u16, available: usize }, // main_data_begin reaches before the data we have
+    MainDataOverrun, // part2_3_length runs past the end of the main data
+    ScalefactorOverrun, // scalefactors take more than part2_3_length
+    UnsupportedLayer(Layer),
+    RiffTooLarge, // WAV output would pass the 4GB RIFF size limit
+    BadWavSpec, // no channels or sampling rate, or too many to describe
+    GainClips { steps: i16, max: i16 }, // the peak allows at most `max` steps
+    BadApeTag,
+    FrameTooSmall(u16), // a VBR header doesn't fit in a frame of this size
+    BadFrameRange { start: u64, end: u64, frames: u64 }, // edit range outside the stream
+    Incompatible { input: usize, frame: u64, mismatch: Mismatch }, // streams can't be joined
+    TagTooLarge(usize), // ID3v2 tags are limited to 256MB
+    BadFrameId, // not a valid ID3v2 frame id for the operation
+    Id3Conversion, // compressed or encrypted ID3v2 frames can't change version
+}
+
+// How a frame differs from the stream it is joined to, as (expected, found).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Version(Version, Version),
+    Layer(Layer, Layer),
+    SamplingRate(u16, u16),
+    ChannelMode(ChannelMode, ChannelMode),
+}
+
+// Header fields that can hold reserved values.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Field {
+    Version,
+    Layer,
+    SamplingRate,
 }
 
+impl MpError {
+    pub fn new(kind: ErrorKind) -> MpError {
+        MpError {
+            kind,
+            location: None,
+        }
+    }
+
+    // Attaches the stream location, keeping one that is already there.
+    pub fn at(mut self, location: Location) -> MpError {
+        if self.location.is_none() {
+            self.location = Some(location);
+        }
+        self
+    }
+
+    #[inline]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    #[inline]
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    // Whether reading can continue with the next frame. Fatal errors mean the source is
+    // exhausted or unreadable.
+    pub fn is_recoverable(&self) -> bool {
+        match self.kind {
+            #[cfg(feature = "std")]
+            ErrorKind::IOError(_) => false,
+            ErrorKind::EOF | ErrorKind::TruncatedFrame => false,
+            _ => true,
+        }
+    }
+
+    #[inline]
+    pub fn is_eof(&self) -> bool {
+        matches!(self.kind, ErrorKind::EOF)
+    }
+}
+
+impl From<ErrorKind> for MpError {
+    fn from(kind: ErrorKind) -> MpError {
+        MpError::new(kind)
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<io::Error> for MpError {
     fn from(io: io::Error) -> MpError {
-        MpError::IOError(io)
+        if io.kind() == io::ErrorKind::UnexpectedEof {
+            MpError::new(ErrorKind::EOF)
+        } else {
+            MpError::new(ErrorKind::IOError(io))
+        }
     }
 }
 
-pub fn wrong_layer(layer: &Layer) -> String {
-    format!("Layer {} is currently unsupported", layer)
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Field::Version => "version",
+            Field::Layer => "layer",
+            Field::SamplingRate => "sampling rate",
+        })
+    }
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Version(expected, found) => write!(f, "{:?} instead of {:?}", found, expected),
+            Mismatch::Layer(expected, found) => write!(f, "{} instead of {}", found, expected),
+            Mismatch::SamplingRate(expected, found) => write!(f, "{}Hz instead of {}Hz", found, expected),
+            Mismatch::ChannelMode(expected, found) => write!(f, "{:?} instead of {:?}", found, expected),
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            ErrorKind::IOError(io) => write!(f, "I/O error: {}", io),
+            ErrorKind::EOF => write!(f, "end of stream"),
+            ErrorKind::NotSeekable => write!(f, "source can't seek"),
+            ErrorKind::SyncLost => write!(f, "lost sync, no frame header found"),
+            ErrorKind::Reserved(field) => write!(f, "reserved {} in header", field),
+            ErrorKind::BadBitrate(bit) => write!(f, "forbidden bitrate index {}", bit),
+            ErrorKind::BadBitrateForMode { bitrate, channel, allowed } => write!(
+                f, "{} kbps is not allowed for {:?}, only for {:?}", bitrate, channel, allowed,
+            ),
+            ErrorKind::BadHeaderLength(length) => write!(f, "header is {} instead of 4 bytes", length),
+            ErrorKind::FrameTooLarge(size) => write!(f, "frame size too large: {}", size),
+            ErrorKind::FreeFormat => write!(f, "free format stream without a VBR header"),
+            ErrorKind::TruncatedFrame => write!(f, "stream ends in the middle of a frame"),
+            ErrorKind::CrcMismatch { expected, actual } => write!(
+                f, "CRC mismatch, stored {:04x} but computed {:04x}", expected, actual,
+            ),
+            ErrorKind::ReservoirUnderflow { needed, available } => write!(
+                f, "main data begins {} bytes back but only {} are available", needed, available,
+            ),
+            ErrorKind::MainDataOverrun => write!(f, "part2_3_length runs past the end of the main data"),
+            ErrorKind::ScalefactorOverrun => write!(f, "scalefactors overrun part2_3_length"),
+            ErrorKind::UnsupportedLayer(layer) => write!(f, "{} is currently unsupported", layer),
+            ErrorKind::RiffTooLarge => write!(f, "WAV data too large for a RIFF file"),
+            ErrorKind::BadWavSpec => write!(f, "WAV format needs channels and a sampling rate that fit its fields"),
+            ErrorKind::GainClips { steps, max } => write!(
+                f, "gain change of {} steps would clip, at most {} are possible", steps, max,
+            ),
+            ErrorKind::BadApeTag => write!(f, "malformed APEv2 tag"),
+            ErrorKind::FrameTooSmall(size) => write!(f, "VBR header doesn't fit in a {} byte frame", size),
+            ErrorKind::BadFrameRange { start, end, frames } => write!(
+                f, "frame range {}..{} is outside the stream of {} frames", start, end, frames,
+            ),
+            ErrorKind::Incompatible { input, frame, mismatch } => write!(
+                f, "input {} can't be joined, frame {} is {}", input, frame, mismatch,
+            ),
+            ErrorKind::TagTooLarge(size) => write!(f, "ID3v2 tag of {} bytes is too large", size),
+            ErrorKind::BadFrameId => write!(f, "invalid ID3v2 frame id"),
+            ErrorKind::Id3Conversion => write!(f, "compressed or encrypted ID3v2 frames can't be converted to another version"),
+        }
+    }
+}
+
+impl Display for MpError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "{} (frame {}, offset {})", self.kind, location.frame, location.offset),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for MpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.kind {
+            ErrorKind::IOError(ref io) => Some(io),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,381 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::vec::Vec;
+
+use crc16::{State, ARC};
+
+use ::ape::ApeTag;
+use ::decoder::FrameReader;
+use ::error::{MpError, ErrorKind};
+use ::header::{Header, CHECKSUM_SIZE, HEADER_SIZE};
+use ::probe::{self, Scan};
+use ::side_info::{self, GranuleChannel, SideInformation};
+use ::source::{Bounded, SetLen};
+use ::tags::Tags;
+use ::vbr::{self, VbrHeader, LAME_SIZE};
+
+// A `global_gain` step changes the output level by 2^(1/4), about 1.5 dB.
+pub const GAIN_STEP_DB: f64 = 1.5;
+
+// APEv2 items mp3gain uses, so either tool can undo the other's changes. Like mp3gain, the undo
+// record holds the change that reverts the gain, the opposite of what was applied.
+const UNDO_KEY: &str = "MP3GAIN_UNDO";
+const MINMAX_KEY: &str = "MP3GAIN_MINMAX";
+
+// Gain state of a stream, before or after a change.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GainInfo {
+    frames: u64, // Audio frames, not counting a VBR header frame
+    gains: Option<(u8, u8)>, // Smallest and largest global_gain of any audible granule
+    undo: i16, // Steps applied so far, the negated undo record
+    peak: Option<f32>, // Current peak amplitude, from the LAME header adjusted by `undo`
+    clamped: u64, // Granules the last change clamped to 0 or 255
+}
+
+impl GainInfo {
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // `None` if every granule is silent.
+    #[inline]
+    pub fn min_gain(&self) -> Option<u8> {
+        self.gains.map(|(min, _)| min)
+    }
+
+    #[inline]
+    pub fn max_gain(&self) -> Option<u8> {
+        self.gains.map(|(_, max)| max)
+    }
+
+    #[inline]
+    pub fn undo(&self) -> i16 {
+        self.undo
+    }
+
+    #[inline]
+    pub fn peak(&self) -> Option<f32> {
+        self.peak
+    }
+
+    // Granules whose global_gain the last change clamped, which undoing it can't restore.
+    #[inline]
+    pub fn clamped(&self) -> u64 {
+        self.clamped
+    }
+
+    // Most negative change that keeps every global_gain at or above 0, so nothing is clamped.
+    pub fn min_steps(&self) -> i16 {
+        self.gains.map_or(i16::MIN, |(min, _)| -(min as i16))
+    }
+
+    // Largest change that keeps every global_gain at or below 255 and, when the peak is known,
+    // doesn't push it past full scale.
+    pub fn max_steps(&self) -> i16 {
+        let range = self.gains.map_or(i16::MAX, |(_, max)| 255 - max as i16);
+        match self.peak {
+            Some(peak) => range.min((-4.0 * (peak as f64).log2()).floor() as i16),
+            None => range,
+        }
+    }
+}
+
+// Reads the gain state without changing anything.
+pub fn analyze<F: Read + Seek>(file: &mut F) -> Result<GainInfo, MpError> {
+    Ok(Stream::read(file)?.info())
+}
+
+// Changes the level of every granule by `steps` * 1.5 dB without touching the main data, and
+// takes the change into the APEv2 undo record. Like mp3gain, a global_gain that would leave 0..=255
+// is clamped, see `GainInfo::clamped`. Errors with `GainClips` if the LAME peak says the result
+// would clip, unless `allow_clipping` is set. A valid LAME music CRC is updated.
+pub fn apply_gain<F: Read + Write + Seek + SetLen>(file: &mut F, steps: i16, allow_clipping: bool) -> Result<GainInfo, MpError> {
+    let mut stream = Stream::read(file)?;
+    let info = stream.info();
+    if !allow_clipping && steps > info.max_steps() {
+        return Err(ErrorKind::GainClips {
+            steps,
+            max: info.max_steps(),
+        }.into());
+    }
+
+    stream.rewrite(file, steps)?;
+    stream.undo += steps;
+    stream.write_undo(file)?;
+    Ok(stream.info())
+}
+
+// Reverts every change recorded in the undo record and removes the record.
+pub fn undo_gain<F: Read + Write + Seek + SetLen>(file: &mut F) -> Result<GainInfo, MpError> {
+    let mut stream = Stream::read(file)?;
+    let steps = -stream.undo;
+    stream.rewrite(file, steps)?;
+    stream.undo = 0;
+    stream.write_undo(file)?;
+    Ok(stream.info())
+}
+
+// Everything a gain change needs to know about the file.
+struct Stream {
+    frames: Vec<(u64, Header, SideInformation)>, // Offset, header and side information
+    gains: Option<(u8, u8)>,
+    undo: i16,
+    peak: Option<f32>, // From the LAME header, before any change
+    clamped: u64,
+    music: Option<Music>,
+    tags: Tags,
+    ape: Option<ApeTag>,
+}
+
+impl Stream {
+    fn read<F: Read + Seek>(file: &mut F) -> Result<Stream, MpError> {
+        let info = probe::probe(file, Scan::Bitrate)?;
+        let tags = info.tags().clone();
+        // The VBR header frame is metadata, and its LAME CRC covers the side information.
+        let skip = if info.vbr_header().is_some() { Some(info.first_frame()) } else { None };
+        let peak = match info.vbr_header() {
+            Some(VbrHeader::Xing(xing)) => xing.lame().and_then(|lame| lame.peak()),
+            _ => None,
+        };
+
+        let ape = match tags.ape() {
+            Some(range) => {
+                let mut data = vec![0u8; (range.end - range.start) as usize];
+                file.seek(SeekFrom::Start(range.start))?;
+                file.read_exact(&mut data)?;
+                Some(ApeTag::parse(&data).ok_or(ErrorKind::BadApeTag)?)
+            },
+            None => None,
+        };
+        let undo = ape.as_ref().and_then(|ape| ape.text(UNDO_KEY)).map_or(0, parse_undo);
+        let music = Music::locate(file, info.first_frame(), info.vbr_header(), tags.audio().end)?;
+
+        let mut frames = Vec::new();
+        file.seek(SeekFrom::Start(tags.audio().start))?;
+        let mut frame_reader = FrameReader::new(Bounded::new(file, tags.audio().end)?);
+        loop {
+            let frame = match frame_reader.advance() {
+                Ok(frame) => frame,
+                Err(ref err) if err.is_eof() => break,
+                Err(ref err) if err.is_recoverable() => continue,
+                Err(err) => return Err(err),
+            };
+            frames.push((frame.offset(), frame.header().clone(), frame.side_information().clone()));
+        }
+
+        // Like `probe::find_first_header`, only frames followed by a header of the same format, or
+        // ending with the audio, count. A false sync in junk rarely is, and rewriting it would
+        // change bytes that aren't audio.
+        let chained: Vec<bool> = (0..frames.len()).map(|index| {
+            let (offset, ref header, _) = frames[index];
+            let end = offset + header.frame_size() as u64;
+            match frames.get(index + 1) {
+                Some(&(next, ref next_header, _)) => next == end
+                    && next_header.version() == header.version()
+                    && next_header.layer() == header.layer()
+                    && next_header.sampling_rate() == header.sampling_rate(),
+                None => end == tags.audio().end,
+            }
+        }).collect();
+        let mut chained = chained.into_iter();
+        frames.retain(|&(offset, _, _)| chained.next() == Some(true) && Some(offset) != skip);
+
+        if frames.is_empty() {
+            return Err(ErrorKind::SyncLost.into());
+        }
+
+        let mut stream = Stream {
+            frames,
+            gains: None,
+            undo,
+            peak,
+            clamped: 0,
+            music,
+            tags,
+            ape,
+        };
+        stream.update_gains();
+        Ok(stream)
+    }
+
+    // Smallest and largest gain of the audible granules.
+    fn update_gains(&mut self) {
+        self.gains = None;
+        for (_, _, side_information) in &self.frames {
+            for granule in side_information.granules() {
+                for ch in 0..side_information.channels() {
+                    let channel = granule.channel(ch);
+                    if audible(&channel) {
+                        let gain = channel.global_gain() as u8;
+                        self.gains = Some(match self.gains {
+                            Some((min, max)) => (min.min(gain), max.max(gain)),
+                            None => (gain, gain),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn info(&self) -> GainInfo {
+        GainInfo {
+            frames: self.frames.len() as u64,
+            gains: self.gains,
+            undo: self.undo,
+            peak: self.peak.map(|peak| peak * 2f32.powf(self.undo as f32 / 4.0)),
+            clamped: self.clamped,
+        }
+    }
+
+    // Writes every frame's header and side information back with the gain of every audible
+    // granule changed by `steps`, clamped to 0..=255, then the LAME music CRC if it was valid.
+    fn rewrite<F: Read + Write + Seek>(&mut self, file: &mut F, steps: i16) -> Result<(), MpError> {
+        let mut buffer = [0u8; HEADER_SIZE + CHECKSUM_SIZE + 32];
+        self.clamped = 0;
+        for (offset, header, side_information) in self.frames.iter_mut() {
+            let channels = side_information.channels();
+            for granule in side_information.granules_mut() {
+                for ch in 0..channels {
+                    let channel = granule.channel(ch);
+                    if audible(&channel) {
+                        let gain = channel.global_gain() as i16 + steps;
+                        if !(0..=255).contains(&gain) {
+                            self.clamped += 1;
+                        }
+                        granule.set_global_gain(ch, gain.clamp(0, 255) as u8);
+                    }
+                }
+            }
+
+            let length = side_info::write_frame_head(header, side_information, &mut buffer)?;
+            file.seek(SeekFrom::Start(*offset))?;
+            file.write_all(&buffer[..length])?;
+        }
+
+        if let Some(ref music) = self.music {
+            music.update(file)?;
+        }
+        self.update_gains();
+        Ok(())
+    }
+
+    // Rewrites the tags after the audio with the undo record updated, keeping an ID3v1 tag last.
+    fn write_undo<F: Read + Write + Seek + SetLen>(&mut self, file: &mut F) -> Result<(), MpError> {
+        let mut ape = self.ape.take().unwrap_or_default();
+        if self.undo == 0 {
+            ape.remove(UNDO_KEY);
+            ape.remove(MINMAX_KEY);
+        } else {
+            ape.set_text(UNDO_KEY, &format!("{:+04},{:+04},N", -self.undo, -self.undo));
+            let (min, max) = self.gains.unwrap_or((0, 0));
+            ape.set_text(MINMAX_KEY, &format!("{:03},{:03}", min, max));
+        }
+
+        let mut tail = Vec::new();
+        if !ape.items().is_empty() {
+            tail.extend_from_slice(&ape.to_bytes());
+        }
+        if let Some(id3v1) = self.tags.id3v1() {
+            let mut data = vec![0u8; (id3v1.end - id3v1.start) as usize];
+            file.seek(SeekFrom::Start(id3v1.start))?;
+            file.read_exact(&mut data)?;
+            tail.extend_from_slice(&data);
+        }
+
+        let start = self.tags.audio().end;
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(&tail)?;
+        file.set_len(start + tail.len() as u64)?;
+        file.flush()?;
+        self.ape = Some(ape);
+        Ok(())
+    }
+}
+
+// Where the LAME header's music CRC and the audio it covers are. Only kept if the stored CRC
+// matches, some encoders leave it zeroed.
+struct Music {
+    frame: u64, // Offset of the Xing frame
+    lame: u64, // Offset of the LAME extension
+    start: u64, // First byte after the Xing frame
+    end: u64, // End of the music, as given by its length
+}
+
+impl Music {
+    fn locate<F: Read + Seek>(file: &mut F, first_frame: u64, vbr_header: Option<&VbrHeader>, audio_end: u64) -> Result<Option<Music>, MpError> {
+        let lame = match vbr_header {
+            Some(VbrHeader::Xing(xing)) => match xing.lame() {
+                Some(lame) if lame.music_length() > 0 => *lame,
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let mut header_bytes = [0u8; HEADER_SIZE];
+        file.seek(SeekFrom::Start(first_frame))?;
+        file.read_exact(&mut header_bytes)?;
+        let header = Header::new(&header_bytes)?;
+        let mut frame = vec![0u8; (header.frame_size() as u64).min(audio_end - first_frame) as usize];
+        file.seek(SeekFrom::Start(first_frame))?;
+        file.read_exact(&mut frame)?;
+        let offset = match vbr::lame_offset(&header, &frame) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        let music = Music {
+            frame: first_frame,
+            lame: first_frame + offset as u64,
+            start: first_frame + header.frame_size() as u64,
+            end: audio_end.min(first_frame + lame.music_length() as u64),
+        };
+
+        if music.start > music.end || music.crc(file)? != lame.music_crc() {
+            return Ok(None);
+        }
+        Ok(Some(music))
+    }
+
+    // CRC-16 (ARC) of the audio frames.
+    fn crc<F: Read + Seek>(&self, file: &mut F) -> Result<u16, MpError> {
+        let mut state = State::<ARC>::new();
+        let mut buffer = [0u8; 4096];
+        let mut position = self.start;
+        file.seek(SeekFrom::Start(position))?;
+        while position < self.end {
+            let length = ((self.end - position) as usize).min(buffer.len());
+            file.read_exact(&mut buffer[..length])?;
+            state.update(&buffer[..length]);
+            position += length as u64;
+        }
+        Ok(state.get())
+    }
+
+    // Stores the music CRC of the audio as it is now, and the LAME CRC that covers it.
+    fn update<F: Read + Write + Seek>(&self, file: &mut F) -> Result<(), MpError> {
+        let music_crc = self.crc(file)?;
+        let crc_offset = self.lame + LAME_SIZE as u64 - 2;
+        let mut frame = vec![0u8; (crc_offset - self.frame) as usize];
+        file.seek(SeekFrom::Start(self.frame))?;
+        file.read_exact(&mut frame)?;
+        let music_crc_offset = frame.len() - 2;
+        frame[music_crc_offset..].copy_from_slice(&music_crc.to_be_bytes());
+
+        file.seek(SeekFrom::Start(self.frame))?;
+        file.write_all(&frame)?;
+        file.write_all(&State::<ARC>::calculate(&frame).to_be_bytes())?;
+        Ok(())
+    }
+}
+
+// Granules without any main data decode to silence whatever their gain, so they are left alone
+// and don't limit the range.
+fn audible(channel: &GranuleChannel) -> bool {
+    channel.part2_3_length() > 0
+}
+
+// Steps applied according to an "+003,+003,N" record, which undoes a change of -3, taking the
+// first (left) channel's change.
+fn parse_undo(record: &str) -> i16 {
+    record.split(',').next().and_then(|steps| steps.trim().parse::<i16>().ok()).map_or(0, |steps| -steps)
+}
@@ -1,14 +1,20 @@
 
-use ::error::MpError;
+use ::error::{MpError, ErrorKind, Field};
 use ::tables::{BITRATE_INDEX, SAMPLING_RATE};
 
-use std::fmt::{self, Display, Formatter};
+use core::fmt::{self, Display, Formatter};
 
 // MP3 headers are 4 bytes long.
 pub const HEADER_SIZE: usize = 4;
 // CRC16 checksums are 2 bytes long.
 pub const CHECKSUM_SIZE: usize = 2;
 
+// Whether the bytes start with the 11 bit frame sync.
+#[inline]
+pub fn sync(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Version {
     Version2_5, // unofficial version for very low bitrate files
@@ -67,6 +73,30 @@ pub enum ChannelMode {
     Mono,
 }
 
+impl ChannelMode {
+    // Number of coded channels.
+    pub fn channels(&self) -> usize {
+        match *self {
+            ChannelMode::Mono => 1,
+            _ => 2,
+        }
+    }
+
+    // Whether both modes decode to the same channel layout. Encoders switch between stereo and
+    // joint stereo from frame to frame, so those count as the same.
+    pub fn same_layout(&self, other: &ChannelMode) -> bool {
+        matches!(
+            (self, other),
+            (ChannelMode::Stereo, ChannelMode::Stereo) |
+            (ChannelMode::Stereo, ChannelMode::JointStereo(_)) |
+            (ChannelMode::JointStereo(_), ChannelMode::Stereo) |
+            (ChannelMode::JointStereo(_), ChannelMode::JointStereo(_)) |
+            (ChannelMode::Dual, ChannelMode::Dual) |
+            (ChannelMode::Mono, ChannelMode::Mono)
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Extension {
     Bands(u8), // Bands (Minimum -> 31)
@@ -86,12 +116,13 @@ pub struct Header {
     original: bool, // Is the original copy (0 = copy, 1 = original)
     emphasis: u8, // 00 = none, 01 = 50/15 ms, 10 = reserved, 11 = CCIT J.17, rarely used
     channel: ChannelMode, // Mono, Dual, Stereo, JointStereo
+    mode_extension: u8, // As stored, only meaningful for joint stereo but kept for `to_bytes`
 }
 
 impl Header {
     pub fn new(data: &[u8]) -> Result<Header, MpError> {
         if data.len() != 4 {
-            return Err(MpError::InvalidData(format!("Header is {} instead of {} bytes", data.len(), HEADER_SIZE)));
+            return Err(ErrorKind::BadHeaderLength(data.len()).into());
         }
 
         let version = ((data[1] & 0b0001_1000) >> 3).into();
@@ -120,8 +151,9 @@ impl Header {
             },
             0b1000_0000 => ChannelMode::Dual,
             0b1100_0000 => ChannelMode::Mono,
-            channel_bits => return Err(MpError::InvalidData(format!("channel data: {:b}, got: {:b}", data[3] & 0b1100_0000, channel_bits))),
+            _ => unreachable!(),
         };
+        let mode_extension = (data[3] & 0b0011_0000) >> 4;
         let copyright = (data[3] & 0b0000_1000) == 0b0000_1000;
         let original = (data[3] & 0b0000_0100) == 0b0000_0100;
         let emphasis = data[3] & 0b0000_0011;
@@ -130,61 +162,63 @@ impl Header {
         let sampling_rate = Header::lookup_sampling_rate(sampling_index, &version)?;
 
         Ok(Header {
-            version: version,
-            layer: layer,
-            protection: protection,
-            bitrate: bitrate,
-            sampling_rate: sampling_rate,
-            padding: padding,
-            private: private,
-            copyright: copyright,
-            original: original,
-            emphasis: emphasis,
-            channel: channel,
+            version,
+            layer,
+            protection,
+            bitrate,
+            sampling_rate,
+            padding,
+            private,
+            copyright,
+            original,
+            emphasis,
+            channel,
+            mode_extension,
         })
     }
 
     // Returns the bitrate of the header.
     pub fn lookup_bitrate(bit: u8, version: &Version, layer: &Layer, channel: &ChannelMode) -> Result<u16, MpError> {
-        if version == &Version::Reserved || layer == &Layer::Reserved {
-            return Err(MpError::Reserved);
+        if version == &Version::Reserved {
+            return Err(ErrorKind::Reserved(Field::Version).into());
         }
 
-        let column_index = match (version, layer) {
-            (&Version::Version1, &Layer::Layer1) => 0,
-            (&Version::Version1, &Layer::Layer2) => 1,
-            (&Version::Version1, &Layer::Layer3) => 2,
-            (_, &Layer::Layer1) => 3,
-            (_, _) => 4,
-        };
+        if layer == &Layer::Reserved {
+            return Err(ErrorKind::Reserved(Field::Layer).into());
+        }
+
+        let column_index = bitrate_column(version, layer);
 
         if bit > 14 {
-            return Err(MpError::BadBit(bit as u16)); // bit index was too high
+            return Err(ErrorKind::BadBitrate(bit).into()); // bit index was too high
         }
 
         let bitrate = BITRATE_INDEX[column_index][bit as usize];
 
         if layer == &Layer::Layer2 {
             // if the bitrate is 32, 48, 56, or 80 and the channel is not mono, then invalid
-            if ((bitrate >= 32 && bitrate <= 56) || (bitrate == 80)) && channel != &ChannelMode::Mono {
-                return Err(MpError::InvalidMode(channel.clone(), vec![ChannelMode::Mono]));
+            if ((32..=56).contains(&bitrate) || (bitrate == 80)) && channel != &ChannelMode::Mono {
+                return Err(ErrorKind::BadBitrateForMode {
+                    bitrate,
+                    channel: channel.clone(),
+                    allowed: &[ChannelMode::Mono],
+                }.into());
             }
             
             if bitrate >= 224 {
                 match channel {
                     &ChannelMode::JointStereo(Extension::Stereo(false, _)) |
                     &ChannelMode::Mono => {
-                        return Err(
-                            MpError::InvalidMode(
-                                channel.clone(), 
-                                vec![
-                                    ChannelMode::Dual,
-                                    ChannelMode::Stereo,
-                                    ChannelMode::JointStereo(Extension::Stereo(true, false)),
-                                    ChannelMode::JointStereo(Extension::Stereo(true, true)),
-                                ],
-                            )
-                        );
+                        return Err(ErrorKind::BadBitrateForMode {
+                            bitrate,
+                            channel: channel.clone(),
+                            allowed: &[
+                                ChannelMode::Dual,
+                                ChannelMode::Stereo,
+                                ChannelMode::JointStereo(Extension::Stereo(true, false)),
+                                ChannelMode::JointStereo(Extension::Stereo(true, true)),
+                            ],
+                        }.into());
                     },
                     _ => (),
 
@@ -198,24 +232,80 @@ impl Header {
     // Returns the sampling rate from the version and the sampling index. Errors if takes in reserved values.
     pub fn lookup_sampling_rate(bit: u8, version: &Version) -> Result<u16, MpError> {
         if bit == 3 {
-            Err(MpError::Reserved)
+            Err(ErrorKind::Reserved(Field::SamplingRate).into())
         } else {
-            let index = match version {
-                &Version::Version1 => 0,
-                &Version::Version2 => 1,
-                &Version::Version2_5 => 2,
-                _ => return Err(MpError::Reserved),
+            let index = match *version {
+                Version::Version1 => 0,
+                Version::Version2 => 1,
+                Version::Version2_5 => 2,
+                _ => return Err(ErrorKind::Reserved(Field::Version).into()),
             };
             Ok(SAMPLING_RATE[index][bit as usize])
         }
     }
 
+    // Serializes the header back into its 4 bytes. Other channel modes than joint stereo keep the
+    // mode extension bits they were parsed with.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let version: u8 = match self.version {
+            Version::Version2_5 => 0,
+            Version::Reserved => 1,
+            Version::Version2 => 2,
+            Version::Version1 => 3,
+        };
+        let layer: u8 = match self.layer {
+            Layer::Reserved => 0,
+            Layer::Layer3 => 1,
+            Layer::Layer2 => 2,
+            Layer::Layer1 => 3,
+        };
+        let bitrate_index = BITRATE_INDEX[bitrate_column(&self.version, &self.layer)].iter()
+            .position(|&bitrate| bitrate == self.bitrate)
+            .unwrap_or(0) as u8;
+        let sampling_row = match self.version {
+            Version::Version1 => 0,
+            Version::Version2 => 1,
+            _ => 2,
+        };
+        let sampling_index = SAMPLING_RATE[sampling_row].iter()
+            .position(|&sampling_rate| sampling_rate == self.sampling_rate)
+            .unwrap_or(3) as u8;
+        let (mode, extension): (u8, u8) = match self.channel {
+            ChannelMode::Stereo => (0, self.mode_extension),
+            ChannelMode::JointStereo(Extension::Stereo(intensity, ms)) => (1, (intensity as u8) << 1 | ms as u8),
+            ChannelMode::JointStereo(Extension::Bands(bands)) => (1, bands.saturating_sub(4) >> 2),
+            ChannelMode::Dual => (2, self.mode_extension),
+            ChannelMode::Mono => (3, self.mode_extension),
+        };
+
+        [
+            0xFF,
+            0xE0 | version << 3 | layer << 1 | !self.protection as u8,
+            bitrate_index << 4 | sampling_index << 2 | self.padding << 1 | self.private as u8,
+            mode << 6 | extension << 4 | (self.copyright as u8) << 3 | (self.original as u8) << 2 | self.emphasis,
+        ]
+    }
+
     // Returns the frame size based on this header.
     pub fn frame_size(&self) -> u16 {
-        if self.layer == Layer::Layer1 {
-            ((12 * (self.bitrate as u32 * 1000) / self.sampling_rate as u32 + self.padding as u32) * 4) as u16
-        } else {
-            (144 * (self.bitrate as u32 * 1000) / self.sampling_rate as u32 + self.padding as u32) as u16
+        match (self.layer, self.version) {
+            (Layer::Layer1, _) => {
+                ((12 * (self.bitrate as u32 * 1000) / self.sampling_rate as u32 + self.padding as u32) * 4) as u16
+            },
+            // MPEG 2 and 2.5 Layer III frames hold half the samples, so half the bytes.
+            (Layer::Layer3, Version::Version2) | (Layer::Layer3, Version::Version2_5) => {
+                (72 * (self.bitrate as u32 * 1000) / self.sampling_rate as u32 + self.padding as u32) as u16
+            },
+            _ => (144 * (self.bitrate as u32 * 1000) / self.sampling_rate as u32 + self.padding as u32) as u16,
+        }
+    }
+
+    // Returns the number of samples per channel in a frame with this header.
+    pub fn samples_per_frame(&self) -> u16 {
+        match (self.layer, self.version) {
+            (Layer::Layer1, _) => 384,
+            (Layer::Layer3, Version::Version2) | (Layer::Layer3, Version::Version2_5) => 576,
+            _ => 1152,
         }
     }
 
@@ -264,57 +354,24 @@ impl Header {
         self.original
     }
 
+    #[inline]
+    pub fn emphasis(&self) -> u8 {
+        self.emphasis
+    }
+
     #[inline]
     pub fn channel(&self) -> &ChannelMode {
         &self.channel
     }
 }
 
-pub fn max_frame_size() {
-    let mp3_possibilites: Vec<(Version, Layer)> = vec![
-        (Version::Version1, Layer::Layer3),
-        (Version::Version2, Layer::Layer3),
-        (Version::Version2_5, Layer::Layer3),
-    ];
-
-    let bitrate_possibilities = 0..0b1111;
-    let sampling_possibilities = 0..0b11;
-
-    for (version, layer) in mp3_possibilites {
-        for bitrate_index in bitrate_possibilities.clone() {
-            for sampling_index in sampling_possibilities.clone() {
-                let bitrate = match Header::lookup_bitrate(bitrate_index, &version, &layer) {
-                    Ok(bitrate) => bitrate,
-                    Err(err) => {
-                        dbg!(err);
-                        continue;
-                    }
-                };
-
-                let sampling_rate = match Header::lookup_sampling_rate(sampling_index, &version) {
-                    Ok(sampling_rate) => sampling_rate,
-                    Err(err) => {
-                        dbg!(err);
-                        continue;
-                    }
-                };
-
-                let mut header = Header {
-                    version: version,
-                    layer: layer,
-                    protection: false,
-                    bitrate: bitrate,
-                    sampling_rate: sampling_rate,
-                    padding: 1,
-                    private: false,
-                    copyright: false,
-                    original: false,
-                    emphasis: 0b00,
-                    channel: ChannelMode::Stereo,
-                };
-                let frame_size = header.frame_size();
-                println!("{:9}: {:?} {} {} {}", frame_size, version, layer.clone(), bitrate_index, sampling_index);
-            }
-        }
+// Column of `BITRATE_INDEX` for a version and layer.
+fn bitrate_column(version: &Version, layer: &Layer) -> usize {
+    match (version, layer) {
+        (&Version::Version1, &Layer::Layer1) => 0,
+        (&Version::Version1, &Layer::Layer2) => 1,
+        (&Version::Version1, &Layer::Layer3) => 2,
+        (_, &Layer::Layer1) => 3,
+        (_, _) => 4,
     }
 }
@@ -0,0 +1,634 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str;
+
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use ::error::{MpError, ErrorKind};
+#[cfg(feature = "std")]
+use ::source::SetLen;
+use ::tags::{synchsafe, ID3V2_HEADER_SIZE};
+
+// Frames are 10 bytes of header (id, size, flags) followed by their data.
+const FRAME_HEADER_SIZE: usize = 10;
+
+// Largest tag size a synchsafe integer can hold.
+pub const MAX_TAG_SIZE: usize = (1 << 28) - 1;
+
+// Padding left after a tag when the file has to be rewritten anyway, so later edits fit in place.
+#[cfg(feature = "std")]
+pub const DEFAULT_PADDING: usize = 1024;
+
+// Chunk size for moving the audio when a tag changes size.
+#[cfg(feature = "std")]
+const COPY_CHUNK: usize = 64 * 1024;
+
+// Text encodings of string fields.
+const LATIN1: u8 = 0;
+const UTF16: u8 = 1; // With byte order mark
+const UTF16BE: u8 = 2; // ID3v2.4 only
+const UTF8: u8 = 3; // ID3v2.4 only
+
+// Chapter offsets that aren't used hold all ones.
+pub const NO_OFFSET: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Id3Version {
+    V3, // ID3v2.3, text in ISO-8859-1 or UTF-16
+    V4, // ID3v2.4, text in UTF-8
+}
+
+// An ID3v2 tag. Tags are parsed from ID3v2.3 and v2.4, and written in `version`. Frames that are
+// compressed or encrypted are kept as `Encoded`, unknown ones as `Raw`. Malformed frames are kept
+// as `Raw`, or as `Encoded` if they have format flags. Grouping is dropped. Frame ids aren't
+// translated between versions (TYER vs TDRC for example).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Id3Tag {
+    version: Id3Version,
+    frames: Vec<Id3Frame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Id3Frame {
+    // T*** frames other than TXXX. ID3v2.3 joins several values with '/'.
+    Text { id: [u8; 4], values: Vec<String> },
+    // TXXX
+    UserText { description: String, value: String },
+    // COMM, `language` is an ISO-639-2 code such as "eng".
+    Comment { language: [u8; 3], description: String, text: String },
+    // APIC, `picture_type` 3 is the front cover.
+    Picture { mime_type: String, picture_type: u8, description: String, data: Vec<u8> },
+    // PRIV
+    Private { owner: String, data: Vec<u8> },
+    // CHAP, times in milliseconds and offsets in bytes (`NO_OFFSET` if unused). The sub frames
+    // usually hold a TIT2 title.
+    Chapter { element_id: String, start_time: u32, end_time: u32, start_offset: u32, end_offset: u32, frames: Vec<Id3Frame> },
+    // CTOC, listing chapters (or other tables) by element id.
+    TableOfContents { element_id: String, top_level: bool, ordered: bool, children: Vec<String>, frames: Vec<Id3Frame> },
+    Raw { id: [u8; 4], data: Vec<u8> },
+    // Compressed, encrypted or malformed, kept as stored with its status and format flags, the
+    // decompressed size of ID3v2.3 included. Their meaning and the layout of `data` depend on
+    // the version, so the tag can't change version.
+    Encoded { id: [u8; 4], flags: [u8; 2], data: Vec<u8> },
+}
+
+impl Id3Tag {
+    pub fn new(version: Id3Version) -> Id3Tag {
+        Id3Tag {
+            version,
+            frames: Vec::new(),
+        }
+    }
+
+    // Parses a whole tag, header included, such as the range found by `Tags::locate`. ID3v2.2
+    // and malformed tags give `None`.
+    pub fn parse(data: &[u8]) -> Option<Id3Tag> {
+        if data.len() < ID3V2_HEADER_SIZE as usize || &data[0..3] != b"ID3" {
+            return None;
+        }
+
+        let version = match data[3] {
+            3 => Id3Version::V3,
+            4 => Id3Version::V4,
+            _ => return None,
+        };
+        let flags = data[5];
+        let size = synchsafe(&data[6..10]) as usize;
+        let body = data.get(ID3V2_HEADER_SIZE as usize..ID3V2_HEADER_SIZE as usize + size)?;
+
+        // ID3v2.3 unsynchronises the whole tag, ID3v2.4 each frame (flagged per frame as well).
+        let unsynchronised;
+        let mut body = if flags & 0x80 != 0 && version == Id3Version::V3 {
+            unsynchronised = resynchronise(body);
+            &unsynchronised[..]
+        } else {
+            body
+        };
+
+        if flags & 0x40 != 0 {
+            let extended = match version {
+                Id3Version::V3 => 4 + read_u32(body, 0)? as usize,
+                Id3Version::V4 => synchsafe(body.get(0..4)?) as usize,
+            };
+            body = body.get(extended..)?;
+        }
+
+        Some(Id3Tag {
+            version,
+            frames: parse_frames(version, body)?,
+        })
+    }
+
+    #[inline]
+    pub fn version(&self) -> Id3Version {
+        self.version
+    }
+
+    // Errors with `Id3Conversion` if the version changes and the tag has `Encoded` frames.
+    pub fn set_version(&mut self, version: Id3Version) -> Result<(), MpError> {
+        if version != self.version && has_encoded(&self.frames) {
+            return Err(ErrorKind::Id3Conversion.into());
+        }
+        self.version = version;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn frames(&self) -> &[Id3Frame] {
+        &self.frames
+    }
+
+    #[inline]
+    pub fn frames_mut(&mut self) -> &mut Vec<Id3Frame> {
+        &mut self.frames
+    }
+
+    // First value of a text frame such as "TIT2".
+    pub fn text(&self, id: &str) -> Option<&str> {
+        self.frames.iter().filter_map(|frame| match *frame {
+            Id3Frame::Text { id: ref frame_id, ref values } if &frame_id[..] == id.as_bytes() => values.first(),
+            _ => None,
+        }).next().map(|value| value.as_str())
+    }
+
+    // Sets a text frame, replacing any frame with the same id. Errors with `BadFrameId` unless
+    // `id` is a text frame id, 4 characters A-Z or 0-9 starting with 'T', other than TXXX.
+    pub fn set_text(&mut self, id: &str, value: &str) -> Result<(), MpError> {
+        let bytes = id.as_bytes();
+        if bytes.len() != 4 || !valid_id(bytes) || bytes[0] != b'T' || bytes == b"TXXX" {
+            return Err(ErrorKind::BadFrameId.into());
+        }
+
+        let mut frame_id = [0u8; 4];
+        frame_id.copy_from_slice(bytes);
+        self.remove(id);
+        self.frames.push(Id3Frame::Text {
+            id: frame_id,
+            values: vec![String::from(value)],
+        });
+        Ok(())
+    }
+
+    // Adds a frame after the existing ones, use `set_text` to replace text frames.
+    pub fn add(&mut self, frame: Id3Frame) {
+        self.frames.push(frame);
+    }
+
+    // Removes every frame with this id, returning how many there were.
+    pub fn remove(&mut self, id: &str) -> usize {
+        let before = self.frames.len();
+        self.frames.retain(|frame| &frame.id()[..] != id.as_bytes());
+        before - self.frames.len()
+    }
+
+    // Size of the serialized tag without padding.
+    pub fn size(&self) -> usize {
+        ID3V2_HEADER_SIZE as usize + self.frames_bytes().len()
+    }
+
+    // Serializes the tag in `version`, followed by `padding` zero bytes. Nothing is
+    // unsynchronised, there is no extended header and no footer.
+    pub fn to_bytes(&self, padding: usize) -> Vec<u8> {
+        let frames = self.frames_bytes();
+        let size = (frames.len() + padding).min(MAX_TAG_SIZE);
+        let mut data = Vec::with_capacity(ID3V2_HEADER_SIZE as usize + size);
+        data.extend_from_slice(b"ID3");
+        data.push(match self.version {
+            Id3Version::V3 => 3,
+            Id3Version::V4 => 4,
+        });
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&to_synchsafe(size as u32));
+        data.extend_from_slice(&frames);
+        data.resize(ID3V2_HEADER_SIZE as usize + size, 0);
+        data
+    }
+
+    fn frames_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for frame in &self.frames {
+            frame.write(self.version, &mut data);
+        }
+        data
+    }
+}
+
+impl Id3Frame {
+    pub fn id(&self) -> [u8; 4] {
+        match *self {
+            Id3Frame::Text { id, .. } | Id3Frame::Raw { id, .. } | Id3Frame::Encoded { id, .. } => id,
+            Id3Frame::UserText { .. } => *b"TXXX",
+            Id3Frame::Comment { .. } => *b"COMM",
+            Id3Frame::Picture { .. } => *b"APIC",
+            Id3Frame::Private { .. } => *b"PRIV",
+            Id3Frame::Chapter { .. } => *b"CHAP",
+            Id3Frame::TableOfContents { .. } => *b"CTOC",
+        }
+    }
+
+    fn parse(version: Id3Version, id: [u8; 4], data: &[u8]) -> Option<Id3Frame> {
+        let frame = match &id {
+            b"TXXX" => {
+                let (&encoding, data) = data.split_first()?;
+                let (description, value) = split_string(encoding, data);
+                Id3Frame::UserText {
+                    description: decode(encoding, description)?,
+                    value: decode(encoding, trim_terminator(encoding, value))?,
+                }
+            },
+            _ if id[0] == b'T' => {
+                let (&encoding, data) = data.split_first()?;
+                let mut values = Vec::new();
+                let mut rest = trim_terminator(encoding, data);
+                loop {
+                    let (value, next) = split_string(encoding, rest);
+                    values.push(decode(encoding, value)?);
+                    // ID3v2.3 has a single value, anything after a terminator is junk.
+                    if next.is_empty() || version == Id3Version::V3 {
+                        break;
+                    }
+                    rest = next;
+                }
+                Id3Frame::Text { id, values }
+            },
+            b"COMM" => {
+                let (&encoding, data) = data.split_first()?;
+                let mut language = [0u8; 3];
+                language.copy_from_slice(data.get(0..3)?);
+                let (description, text) = split_string(encoding, &data[3..]);
+                Id3Frame::Comment {
+                    language,
+                    description: decode(encoding, description)?,
+                    text: decode(encoding, trim_terminator(encoding, text))?,
+                }
+            },
+            b"APIC" => {
+                let (&encoding, data) = data.split_first()?;
+                let (mime_type, data) = split_string(LATIN1, data);
+                let (&picture_type, data) = data.split_first()?;
+                let (description, data) = split_string(encoding, data);
+                Id3Frame::Picture {
+                    mime_type: decode(LATIN1, mime_type)?,
+                    picture_type,
+                    description: decode(encoding, description)?,
+                    data: data.to_vec(),
+                }
+            },
+            b"PRIV" => {
+                let (owner, data) = split_string(LATIN1, data);
+                Id3Frame::Private {
+                    owner: decode(LATIN1, owner)?,
+                    data: data.to_vec(),
+                }
+            },
+            b"CHAP" => {
+                let (element_id, data) = split_string(LATIN1, data);
+                Id3Frame::Chapter {
+                    element_id: decode(LATIN1, element_id)?,
+                    start_time: read_u32(data, 0)?,
+                    end_time: read_u32(data, 4)?,
+                    start_offset: read_u32(data, 8)?,
+                    end_offset: read_u32(data, 12)?,
+                    frames: parse_frames(version, &data[16..])?,
+                }
+            },
+            b"CTOC" => {
+                let (element_id, data) = split_string(LATIN1, data);
+                let flags = *data.first()?;
+                let count = *data.get(1)?;
+                let mut rest = data.get(2..)?;
+                let mut children = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let (child, next) = split_string(LATIN1, rest);
+                    children.push(decode(LATIN1, child)?);
+                    rest = next;
+                }
+                Id3Frame::TableOfContents {
+                    element_id: decode(LATIN1, element_id)?,
+                    top_level: flags & 0x02 != 0,
+                    ordered: flags & 0x01 != 0,
+                    children,
+                    frames: parse_frames(version, rest)?,
+                }
+            },
+            _ => Id3Frame::Raw {
+                id,
+                data: data.to_vec(),
+            },
+        };
+        Some(frame)
+    }
+
+    // Appends the frame, header included, to `data`.
+    fn write(&self, version: Id3Version, data: &mut Vec<u8>) {
+        let encoding = match version {
+            Id3Version::V3 => LATIN1,
+            Id3Version::V4 => UTF8,
+        };
+        let mut body = Vec::new();
+        match *self {
+            Id3Frame::Text { ref values, .. } => {
+                let separator = if version == Id3Version::V3 { "/" } else { "\0" };
+                let text = values.join(separator);
+                let encoding = text_encoding(encoding, &text);
+                body.push(encoding);
+                encode(encoding, &text, &mut body);
+            },
+            Id3Frame::UserText { ref description, ref value } => {
+                let encoding = text_encoding(text_encoding(encoding, description), value);
+                body.push(encoding);
+                encode_terminated(encoding, description, &mut body);
+                encode(encoding, value, &mut body);
+            },
+            Id3Frame::Comment { ref language, ref description, ref text } => {
+                let encoding = text_encoding(text_encoding(encoding, description), text);
+                body.push(encoding);
+                body.extend_from_slice(language);
+                encode_terminated(encoding, description, &mut body);
+                encode(encoding, text, &mut body);
+            },
+            Id3Frame::Picture { ref mime_type, picture_type, ref description, data: ref picture } => {
+                let encoding = text_encoding(encoding, description);
+                body.push(encoding);
+                encode_terminated(LATIN1, mime_type, &mut body);
+                body.push(picture_type);
+                encode_terminated(encoding, description, &mut body);
+                body.extend_from_slice(picture);
+            },
+            Id3Frame::Private { ref owner, data: ref private } => {
+                encode_terminated(LATIN1, owner, &mut body);
+                body.extend_from_slice(private);
+            },
+            Id3Frame::Chapter { ref element_id, start_time, end_time, start_offset, end_offset, ref frames } => {
+                encode_terminated(LATIN1, element_id, &mut body);
+                for value in &[start_time, end_time, start_offset, end_offset] {
+                    body.extend_from_slice(&value.to_be_bytes());
+                }
+                for frame in frames {
+                    frame.write(version, &mut body);
+                }
+            },
+            Id3Frame::TableOfContents { ref element_id, top_level, ordered, ref children, ref frames } => {
+                encode_terminated(LATIN1, element_id, &mut body);
+                body.push((top_level as u8) << 1 | ordered as u8);
+                body.push(children.len().min(255) as u8);
+                for child in children.iter().take(255) {
+                    encode_terminated(LATIN1, child, &mut body);
+                }
+                for frame in frames {
+                    frame.write(version, &mut body);
+                }
+            },
+            Id3Frame::Raw { data: ref raw, .. } | Id3Frame::Encoded { data: ref raw, .. } => body.extend_from_slice(raw),
+        }
+
+        data.extend_from_slice(&self.id());
+        match version {
+            Id3Version::V3 => data.extend_from_slice(&(body.len() as u32).to_be_bytes()),
+            Id3Version::V4 => data.extend_from_slice(&to_synchsafe(body.len() as u32)),
+        }
+        match *self {
+            Id3Frame::Encoded { flags, .. } => data.extend_from_slice(&flags),
+            _ => data.extend_from_slice(&[0, 0]),
+        }
+        data.extend_from_slice(&body);
+    }
+}
+
+// Writes `tag` in place of the ID3v2 tag (or tags) at the start of a file, or in front of the
+// audio if there is none. A tag that fits in the space of the old one, padding included, is
+// written in place with the rest as padding. Otherwise everything after it is moved to leave
+// room for the tag and `DEFAULT_PADDING`, back if the tag grew or forward if the old tags were
+// too large to reuse. The audio and trailing tags are kept byte for byte. Returns whether the
+// tag was written in place.
+#[cfg(feature = "std")]
+pub fn write_tag<F: Read + Write + Seek + SetLen>(file: &mut F, tag: &Id3Tag) -> Result<bool, MpError> {
+    let tags = ::tags::Tags::locate(&mut *file)?;
+    let space = tags.audio().start as usize;
+    let size = tag.size();
+    if size > MAX_TAG_SIZE {
+        return Err(ErrorKind::TagTooLarge(size).into());
+    }
+
+    if tags.id3v2().is_some() && space >= size && space <= MAX_TAG_SIZE + ID3V2_HEADER_SIZE as usize {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&tag.to_bytes(space - size))?;
+        file.flush()?;
+        return Ok(true);
+    }
+
+    let data = tag.to_bytes(DEFAULT_PADDING.min(MAX_TAG_SIZE + ID3V2_HEADER_SIZE as usize - size));
+    let length = file.seek(SeekFrom::End(0))?;
+    let start = space as u64;
+    let shift = data.len() as u64;
+
+    move_tail(file, start, shift, length)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&data)?;
+    file.set_len(length - start + shift)?;
+    file.flush()?;
+    Ok(false)
+}
+
+// Moves the bytes from `start` to the end of the file (`length`) to `to`. Moving back copies
+// from the end and moving forward from the start, so nothing is overwritten before it was read.
+#[cfg(feature = "std")]
+fn move_tail<F: Read + Write + Seek>(file: &mut F, start: u64, to: u64, length: u64) -> Result<(), MpError> {
+    let size = length - start;
+    let mut buffer = vec![0u8; COPY_CHUNK];
+    let mut moved = 0;
+    while moved < size && to != start {
+        let count = (size - moved).min(COPY_CHUNK as u64);
+        let offset = if to > start { size - moved - count } else { moved };
+        let chunk = &mut buffer[..count as usize];
+        file.seek(SeekFrom::Start(start + offset))?;
+        file.read_exact(chunk)?;
+        file.seek(SeekFrom::Start(to + offset))?;
+        file.write_all(chunk)?;
+        moved += count;
+    }
+    Ok(())
+}
+
+fn parse_frames(version: Id3Version, mut data: &[u8]) -> Option<Vec<Id3Frame>> {
+    let mut frames = Vec::new();
+    // Padding (or junk) starts where a frame id isn't made of A-Z and 0-9.
+    while data.len() >= FRAME_HEADER_SIZE && valid_id(&data[0..4]) {
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&data[0..4]);
+        let size = match version {
+            Id3Version::V3 => read_u32(data, 4)? as usize,
+            Id3Version::V4 => synchsafe(&data[4..8]) as usize,
+        };
+        let flags = [data[8], data[9]];
+        let format = flags[1];
+        let body = data.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + size)?;
+        data = &data[FRAME_HEADER_SIZE + size..];
+
+        let (compressed, encrypted) = match version {
+            Id3Version::V3 => (format & 0x80 != 0, format & 0x40 != 0),
+            Id3Version::V4 => (format & 0x08 != 0, format & 0x04 != 0),
+        };
+        if compressed || encrypted {
+            frames.push(Id3Frame::Encoded {
+                id,
+                flags,
+                data: body.to_vec(),
+            });
+            continue;
+        }
+
+        // The group id, and the data length indicator of ID3v2.4, come before the frame's data.
+        let resynchronised;
+        let content = match version {
+            Id3Version::V3 => body.get(if format & 0x20 != 0 { 1 } else { 0 }..),
+            Id3Version::V4 => {
+                let mut content = body;
+                if format & 0x02 != 0 {
+                    resynchronised = resynchronise(body);
+                    content = &resynchronised[..];
+                }
+                let skip = if format & 0x40 != 0 { 1 } else { 0 } + if format & 0x01 != 0 { 4 } else { 0 };
+                content.get(skip..)
+            },
+        };
+
+        // A malformed frame is passed through rather than losing the whole tag.
+        frames.push(match content.and_then(|content| Id3Frame::parse(version, id, content)) {
+            Some(frame) => frame,
+            None if format == 0 => Id3Frame::Raw { id, data: body.to_vec() },
+            None => Id3Frame::Encoded { id, flags, data: body.to_vec() },
+        });
+    }
+    Some(frames)
+}
+
+fn valid_id(id: &[u8]) -> bool {
+    id.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+}
+
+// Whether any frame, or sub frame of a chapter or table of contents, is `Encoded`.
+fn has_encoded(frames: &[Id3Frame]) -> bool {
+    frames.iter().any(|frame| match *frame {
+        Id3Frame::Encoded { .. } => true,
+        Id3Frame::Chapter { ref frames, .. } | Id3Frame::TableOfContents { ref frames, .. } => has_encoded(frames),
+        _ => false,
+    })
+}
+
+// Undoes unsynchronisation, which put a 0 after every 0xFF.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0) {
+            output.push(byte);
+        }
+        previous = byte;
+    }
+    output
+}
+
+fn to_synchsafe(value: u32) -> [u8; 4] {
+    [
+        (value >> 21) as u8 & 0x7F,
+        (value >> 14) as u8 & 0x7F,
+        (value >> 7) as u8 & 0x7F,
+        value as u8 & 0x7F,
+    ]
+}
+
+fn read_u32(data: &[u8], position: usize) -> Option<u32> {
+    let bytes = data.get(position..position + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// The preferred encoding, unless `text` can't be written in it. Only ISO-8859-1 can fall short,
+// UTF-16 is used instead then.
+fn text_encoding(preferred: u8, text: &str) -> u8 {
+    if preferred == LATIN1 && text.chars().any(|c| c as u32 > 0xFF) {
+        UTF16
+    } else {
+        preferred
+    }
+}
+
+fn encode(encoding: u8, text: &str, data: &mut Vec<u8>) {
+    match encoding {
+        LATIN1 => data.extend(text.chars().map(|c| c as u32 as u8)),
+        UTF16 => {
+            data.extend_from_slice(&[0xFF, 0xFE]);
+            for unit in text.encode_utf16() {
+                data.extend_from_slice(&unit.to_le_bytes());
+            }
+        },
+        UTF16BE => {
+            for unit in text.encode_utf16() {
+                data.extend_from_slice(&unit.to_be_bytes());
+            }
+        },
+        _ => data.extend_from_slice(text.as_bytes()),
+    }
+}
+
+fn encode_terminated(encoding: u8, text: &str, data: &mut Vec<u8>) {
+    encode(encoding, text, data);
+    match encoding {
+        UTF16 | UTF16BE => data.extend_from_slice(&[0, 0]),
+        _ => data.push(0),
+    }
+}
+
+fn decode(encoding: u8, data: &[u8]) -> Option<String> {
+    match encoding {
+        LATIN1 => Some(data.iter().map(|&byte| byte as char).collect()),
+        UTF16 | UTF16BE => {
+            let (big_endian, data) = match data {
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                _ => (encoding == UTF16BE, data),
+            };
+            let units: Vec<u16> = data.chunks_exact(2).map(|pair| {
+                if big_endian { u16::from_be_bytes([pair[0], pair[1]]) } else { u16::from_le_bytes([pair[0], pair[1]]) }
+            }).collect();
+            String::from_utf16(&units).ok()
+        },
+        UTF8 => str::from_utf8(data).ok().map(String::from),
+        _ => None,
+    }
+}
+
+// Splits at the first terminator of the encoding, giving the string and what follows the
+// terminator. Without a terminator everything is the string.
+fn split_string(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+    match encoding {
+        UTF16 | UTF16BE => {
+            match data.chunks_exact(2).position(|pair| pair == [0, 0]) {
+                Some(index) => (&data[..index * 2], &data[index * 2 + 2..]),
+                None => (data, &[]),
+            }
+        },
+        _ => match data.iter().position(|&byte| byte == 0) {
+            Some(index) => (&data[..index], &data[index + 1..]),
+            None => (data, &[]),
+        },
+    }
+}
+
+// Drops terminators at the end of a value, which some taggers write.
+fn trim_terminator(encoding: u8, mut data: &[u8]) -> &[u8] {
+    match encoding {
+        UTF16 | UTF16BE => {
+            while data.len() >= 2 && data[data.len() - 2..] == [0, 0] {
+                data = &data[..data.len() - 2];
+            }
+        },
+        _ => {
+            while data.last() == Some(&0) {
+                data = &data[..data.len() - 1];
+            }
+        },
+    }
+    data
+}
@@ -1,10 +1,36 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "alloc")]
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "std")]
 extern crate byteorder;
-extern crate bitstream_io;
+extern crate crc16;
 
+#[cfg(feature = "alloc")]
+pub mod ape;
+pub mod bits;
+pub mod diagnostics;
 pub mod error;
+#[cfg(feature = "std")]
+pub mod gain;
+pub mod source;
 pub mod tables;
 pub mod decoder;
+#[cfg(feature = "std")]
+pub mod edit;
 pub mod header;
+#[cfg(feature = "alloc")]
+pub mod id3;
+pub mod probe;
+pub mod scalefactors;
 pub mod side_info;
-
+pub mod tags;
+#[cfg(feature = "alloc")]
+pub mod validate;
+pub mod vbr;
+#[cfg(feature = "std")]
+pub mod wav;
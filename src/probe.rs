@@ -0,0 +1,317 @@
+use core::time::Duration;
+
+use ::error::{MpError, ErrorKind};
+use ::header::{self, ChannelMode, Header, Layer, Version, HEADER_SIZE};
+use ::source::ByteSource;
+use ::tags::Tags;
+use ::vbr::{BitrateMode, VbrHeader};
+
+// How many bytes ahead to search for a header, at the start and after losing sync.
+const SEARCH_LIMIT: u64 = 1024 * 10; // 10kB
+
+// Enough of the first frame to hold a Xing header with its LAME extension, or a VBRI header.
+const VBR_HEADER_LIMIT: usize = 512;
+
+// How many frame headers `probe` reads.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scan {
+    Full, // Every frame header
+    Sample(u64), // Only the first n frame headers, extrapolating the rest from their sizes
+    Bitrate, // Only the first frame header, estimating from the audio size and its bitrate
+}
+
+// Where `StreamInfo::duration` came from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DurationMethod {
+    FrameCount, // Every frame was counted
+    VbrHeader, // Frame count declared in the Xing/VBRI header
+    Sampled, // Frame count extrapolated from the average size of the sampled frames
+    Bitrate, // Audio size divided by the first frame's bitrate, only exact for CBR
+}
+
+impl DurationMethod {
+    // Whether the duration is exact rather than an estimate that should be labelled as such.
+    pub fn is_exact(&self) -> bool {
+        match *self {
+            DurationMethod::FrameCount | DurationMethod::VbrHeader => true,
+            DurationMethod::Sampled | DurationMethod::Bitrate => false,
+        }
+    }
+}
+
+// Stream metadata gathered from tags, the VBR header and frame headers, without decoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    version: Version,
+    layer: Layer,
+    sampling_rate: u16,
+    channel: ChannelMode,
+    emphasis: u8,
+    copyright: bool,
+    original: bool,
+    min_bitrate: u16, // kbps
+    max_bitrate: u16, // kbps
+    bitrate_mode: BitrateMode,
+    frames: u64, // Audio frames, not counting a VBR header frame
+    duration: Duration,
+    duration_method: DurationMethod,
+    first_frame: u64, // Byte offset of the first frame, which may hold `vbr_header`
+    vbr_header: Option<VbrHeader>,
+    tags: Tags,
+}
+
+impl StreamInfo {
+    #[inline]
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    #[inline]
+    pub fn layer(&self) -> &Layer {
+        &self.layer
+    }
+
+    #[inline]
+    pub fn sampling_rate(&self) -> u16 {
+        self.sampling_rate
+    }
+
+    #[inline]
+    pub fn channel(&self) -> &ChannelMode {
+        &self.channel
+    }
+
+    #[inline]
+    pub fn emphasis(&self) -> u8 {
+        self.emphasis
+    }
+
+    #[inline]
+    pub fn copyright(&self) -> bool {
+        self.copyright
+    }
+
+    #[inline]
+    pub fn original(&self) -> bool {
+        self.original
+    }
+
+    #[inline]
+    pub fn min_bitrate(&self) -> u16 {
+        self.min_bitrate
+    }
+
+    #[inline]
+    pub fn max_bitrate(&self) -> u16 {
+        self.max_bitrate
+    }
+
+    #[inline]
+    pub fn bitrate_mode(&self) -> BitrateMode {
+        self.bitrate_mode
+    }
+
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    #[inline]
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    #[inline]
+    pub fn duration_method(&self) -> DurationMethod {
+        self.duration_method
+    }
+
+    #[inline]
+    pub fn first_frame(&self) -> u64 {
+        self.first_frame
+    }
+
+    #[inline]
+    pub fn vbr_header(&self) -> Option<&VbrHeader> {
+        self.vbr_header.as_ref()
+    }
+
+    #[inline]
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+}
+
+// Reads the stream's tags, VBR header and frame headers. Format fields come from the first frame.
+// With `Scan::Full` the duration is always counted frame by frame, otherwise a frame count from
+// the VBR header is preferred over estimating. Errors with `FreeFormat` if the stream starts with
+// free format frames and has no VBR header, as neither counting nor estimating is possible.
+pub fn probe<R: ByteSource>(reader: &mut R, scan: Scan) -> Result<StreamInfo, MpError> {
+    let tags = Tags::locate(reader)?;
+    let audio = tags.audio();
+
+    let (first_frame, header) = match find_first_header(reader, audio.start, audio.end)? {
+        Some(found) => found,
+        None => return Err(ErrorKind::SyncLost.into()),
+    };
+
+    let mut frame = [0u8; VBR_HEADER_LIMIT];
+    let frame_length = (header.frame_size() as usize)
+        .min(VBR_HEADER_LIMIT)
+        .min((audio.end - first_frame) as usize);
+    reader.seek(first_frame)?;
+    reader.read_exact(&mut frame[..frame_length])?;
+    let vbr_header = VbrHeader::parse(&header, &frame[..frame_length]);
+
+    let mut position = first_frame;
+    if vbr_header.is_some() {
+        position += header.frame_size() as u64;
+    }
+    let audio_start = position;
+
+    let limit = match scan {
+        Scan::Full => u64::MAX,
+        Scan::Sample(limit) => limit,
+        Scan::Bitrate => 1,
+    };
+
+    let mut frames: u64 = 0;
+    let mut bytes: u64 = 0;
+    let mut seconds = 0.0;
+    let mut min_bitrate = u16::MAX;
+    let mut max_bitrate = 0;
+    let mut complete = true;
+    while position + HEADER_SIZE as u64 <= audio.end {
+        if frames >= limit {
+            complete = false;
+            break;
+        }
+
+        let mut header_bytes = [0u8; HEADER_SIZE];
+        reader.seek(position)?;
+        reader.read_exact(&mut header_bytes)?;
+        let parsed = if header::sync(&header_bytes) { Header::new(&header_bytes).ok() } else { None };
+        let frame_header = match parsed {
+            Some(frame_header) => frame_header,
+            None => match find_header(reader, position + 1, audio.end)? {
+                Some((found, frame_header)) => {
+                    position = found;
+                    frame_header
+                },
+                None => break,
+            },
+        };
+
+        let frame_size = frame_header.frame_size() as u64;
+        if frame_size == 0 {
+            // Free format, frame boundaries can't be derived from the header.
+            complete = false;
+            break;
+        }
+
+        frames += 1;
+        bytes += frame_size.min(audio.end - position);
+        seconds += frame_header.samples_per_frame() as f64 / frame_header.sampling_rate() as f64;
+        min_bitrate = min_bitrate.min(frame_header.bitrate());
+        max_bitrate = max_bitrate.max(frame_header.bitrate());
+        position += frame_size;
+    }
+
+    if frames == 0 {
+        min_bitrate = header.bitrate();
+        max_bitrate = header.bitrate();
+    }
+
+    let seconds_per_frame = header.samples_per_frame() as f64 / header.sampling_rate() as f64;
+    let audio_bytes = audio.end - audio_start;
+    let declared = vbr_header.as_ref().and_then(|vbr_header| vbr_header.frames());
+    let (frames, seconds, duration_method) = if complete {
+        (frames, seconds, DurationMethod::FrameCount)
+    } else if let Some(declared) = declared {
+        (declared as u64, declared as f64 * seconds_per_frame, DurationMethod::VbrHeader)
+    } else if header.bitrate() == 0 && frames == 0 {
+        return Err(ErrorKind::FreeFormat.into());
+    } else if scan == Scan::Bitrate || frames == 0 {
+        let seconds = (audio_bytes * 8) as f64 / (header.bitrate() as f64 * 1000.0);
+        ((seconds / seconds_per_frame + 0.5) as u64, seconds, DurationMethod::Bitrate)
+    } else {
+        let estimate = audio_bytes * frames / bytes;
+        (estimate, estimate as f64 * seconds / frames as f64, DurationMethod::Sampled)
+    };
+
+    let bitrate_mode = match vbr_header {
+        Some(ref vbr_header) => vbr_header.bitrate_mode(),
+        None if min_bitrate == max_bitrate => BitrateMode::Constant,
+        None => BitrateMode::Variable,
+    };
+
+    Ok(StreamInfo {
+        version: *header.version(),
+        layer: *header.layer(),
+        sampling_rate: header.sampling_rate(),
+        channel: header.channel().clone(),
+        emphasis: header.emphasis(),
+        copyright: header.copyright(),
+        original: header.original(),
+        min_bitrate,
+        max_bitrate,
+        bitrate_mode,
+        frames,
+        duration: Duration::from_secs_f64(seconds),
+        duration_method,
+        first_frame,
+        vbr_header,
+        tags,
+    })
+}
+
+// Finds the first header at or after `position` and before `end` that is followed by another
+// header of the same format, so stray sync bytes in junk don't count.
+pub fn find_first_header<R: ByteSource>(reader: &mut R, mut position: u64, end: u64) -> Result<Option<(u64, Header)>, MpError> {
+    while let Some((found, header)) = find_header(reader, position, end)? {
+        let next = found + header.frame_size() as u64;
+        if header.frame_size() == 0 || next + HEADER_SIZE as u64 > end {
+            return Ok(Some((found, header)));
+        }
+
+        let mut next_bytes = [0u8; HEADER_SIZE];
+        reader.seek(next)?;
+        reader.read_exact(&mut next_bytes)?;
+        if let Ok(next_header) = Header::new(&next_bytes) {
+            if header::sync(&next_bytes)
+                && next_header.version() == header.version()
+                && next_header.layer() == header.layer()
+                && next_header.sampling_rate() == header.sampling_rate() {
+                return Ok(Some((found, header)));
+            }
+        }
+
+        position = found + 1;
+    }
+
+    Ok(None)
+}
+
+// Finds the next header at or after `position` and before `end`, looking at most 10kB ahead.
+pub fn find_header<R: ByteSource>(reader: &mut R, position: u64, end: u64) -> Result<Option<(u64, Header)>, MpError> {
+    let mut buffer = [0u8; 1024];
+    let limit = end.min(position + SEARCH_LIMIT);
+    let mut start = position;
+    while start + HEADER_SIZE as u64 <= limit {
+        let length = ((limit - start) as usize).min(buffer.len());
+        reader.seek(start)?;
+        reader.read_exact(&mut buffer[..length])?;
+
+        for index in 0..=length - HEADER_SIZE {
+            if header::sync(&buffer[index..]) {
+                if let Ok(header) = Header::new(&buffer[index..index + HEADER_SIZE]) {
+                    return Ok(Some((start + index as u64, header)));
+                }
+            }
+        }
+
+        start += (length - (HEADER_SIZE - 1)) as u64;
+    }
+
+    Ok(None)
+}
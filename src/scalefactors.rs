@@ -0,0 +1,163 @@
+use ::bits::BitReader;
+use ::error::{MpError, ErrorKind};
+use ::header::{ChannelMode, Extension, Header, Version};
+use ::side_info::{BlockType, GranuleChannel, SideInformation};
+use ::tables::{NR_OF_SFB, SCALE_COMPRESS};
+
+// Long block scalefactor bands covered by each `scfsi` group.
+const SCFSI_BANDS: [(usize, usize); 4] = [(0, 6), (6, 11), (11, 16), (16, 21)];
+
+// Scalefactors of one granule and channel, as stored at the start of its part of the main data.
+// Bands that aren't transmitted (the last long and short band, or the long bands of a short
+// block) are 0.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Scalefactors {
+    long: [u8; 22],
+    short: [[u8; 3]; 13], // Band, window
+    length: u32, // part2_length, bits used by the scalefactors
+}
+
+impl Scalefactors {
+    #[inline]
+    pub fn long(&self) -> &[u8; 22] {
+        &self.long
+    }
+
+    #[inline]
+    pub fn short(&self) -> &[[u8; 3]; 13] {
+        &self.short
+    }
+
+    #[inline]
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+// Reads the scalefactors of every granule and channel from a frame's main data, which starts
+// `main_data_begin` bytes back in the reservoir. Indexed by granule, then channel. MPEG 2 and 2.5
+// frames only fill the first granule.
+pub fn read(header: &Header, side_information: &SideInformation, main_data: &[u8]) -> Result<[[Scalefactors; 2]; 2], MpError> {
+    let lsf = header.version() != &Version::Version1;
+    let intensity_stereo = match *header.channel() {
+        ChannelMode::JointStereo(Extension::Stereo(intensity, _)) => intensity,
+        _ => false,
+    };
+
+    let mut scalefactors = [[Scalefactors::default(); 2]; 2];
+    let channels = header.channel().channels();
+    let mut position = 0;
+    for (gr, granule) in side_information.granules().iter().enumerate() {
+        let previous = if gr > 0 { Some(scalefactors[gr - 1]) } else { None };
+        for (ch, result) in scalefactors[gr].iter_mut().enumerate().take(channels) {
+            let channel = granule.channel(ch);
+            if position + channel.part2_3_length() as usize > main_data.len() * 8 {
+                return Err(ErrorKind::MainDataOverrun.into());
+            }
+
+            let mut reader = BitReader::new(main_data);
+            reader.skip(position as u32)?;
+            let reuse = if gr > 0 { side_information.scfsi()[ch] } else { [false; 4] };
+            *result = if lsf {
+                read_lsf_channel(&mut reader, &channel, intensity_stereo && ch == 1)?
+            } else {
+                read_channel(&mut reader, &channel, reuse, previous.map(|previous| previous[ch]))?
+            };
+            if result.length > channel.part2_3_length() {
+                return Err(ErrorKind::ScalefactorOverrun.into());
+            }
+
+            position += channel.part2_3_length() as usize;
+        }
+    }
+
+    Ok(scalefactors)
+}
+
+fn read_channel(reader: &mut BitReader, channel: &GranuleChannel, reuse: [bool; 4], previous: Option<Scalefactors>) -> Result<Scalefactors, MpError> {
+    let (slen1, slen2) = SCALE_COMPRESS[channel.scalefactor_compress() as usize];
+    let (slen1, slen2) = (slen1 as u32, slen2 as u32);
+    let start = reader.position();
+    let mut scalefactors = Scalefactors::default();
+
+    if channel.windows_switching() && channel.block_type() == BlockType::Short {
+        let mut first_short = 0;
+        if channel.mixed_block() {
+            for band in 0..8 {
+                scalefactors.long[band] = reader.read(slen1)?;
+            }
+            first_short = 3;
+        }
+
+        for band in first_short..12 {
+            let bits = if band < 6 { slen1 } else { slen2 };
+            for window in 0..3 {
+                scalefactors.short[band][window] = reader.read(bits)?;
+            }
+        }
+    } else {
+        for (group, &(first, last)) in SCFSI_BANDS.iter().enumerate() {
+            let bits = if group < 2 { slen1 } else { slen2 };
+            for band in first..last {
+                scalefactors.long[band] = match previous {
+                    Some(ref previous) if reuse[group] => previous.long[band],
+                    _ => reader.read(bits)?,
+                };
+            }
+        }
+    }
+
+    scalefactors.length = (reader.position() - start) as u32;
+    Ok(scalefactors)
+}
+
+// MPEG 2 and 2.5 scalefactors, in up to four partitions whose sizes and bit widths are packed
+// into `scalefactor_compress`. The right channel of intensity stereo packs them differently.
+fn read_lsf_channel(reader: &mut BitReader, channel: &GranuleChannel, intensity_right: bool) -> Result<Scalefactors, MpError> {
+    let compress = channel.scalefactor_compress() as u32;
+    let (table, slen) = if intensity_right {
+        let compress = compress >> 1;
+        if compress < 180 {
+            (3, [compress / 36, compress % 36 / 6, compress % 6, 0])
+        } else if compress < 244 {
+            let compress = compress - 180;
+            (4, [compress >> 4, (compress & 15) >> 2, compress & 3, 0])
+        } else {
+            let compress = compress - 244;
+            (5, [compress / 3, compress % 3, 0, 0])
+        }
+    } else if compress < 400 {
+        (0, [(compress >> 4) / 5, (compress >> 4) % 5, (compress & 15) >> 2, compress & 3])
+    } else if compress < 500 {
+        let compress = compress - 400;
+        (1, [(compress >> 2) / 5, (compress >> 2) % 5, compress & 3, 0])
+    } else {
+        let compress = compress - 500;
+        (2, [compress / 3, compress % 3, 0, 0])
+    };
+
+    let short = channel.windows_switching() && channel.block_type() == BlockType::Short;
+    let mixed = short && channel.mixed_block();
+    let block = if mixed { 2 } else if short { 1 } else { 0 };
+
+    let start = reader.position();
+    let mut scalefactors = Scalefactors::default();
+    // Index into the long bands, then into the short bands by band and window.
+    let mut index = 0;
+    for (&count, &bits) in NR_OF_SFB[table][block].iter().zip(slen.iter()) {
+        for _ in 0..count {
+            let value = reader.read(bits)?;
+            if !short || (mixed && index < 6) {
+                scalefactors.long[index] = value;
+            } else {
+                // Short bands of a mixed block start at band 3, after the 6 long bands.
+                let short_index = if mixed { index - 6 + 9 } else { index };
+                scalefactors.short[short_index / 3][short_index % 3] = value;
+            }
+            index += 1;
+        }
+    }
+
+    scalefactors.length = (reader.position() - start) as u32;
+    Ok(scalefactors)
+}
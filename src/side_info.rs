@@ -1,8 +1,10 @@
 
-use ::error::MpError;
-use ::header::{ChannelMode, Header};
+use ::error::{MpError, ErrorKind};
+use ::header::{ChannelMode, Extension, Header, Version, CHECKSUM_SIZE, HEADER_SIZE};
 
-use bitstream_io::{BitReader, BigEndian};
+use ::bits::{BitReader, BitWriter};
+
+use crc16::{State, BUYPASS};
 
 // Technically this is 1441 - side info size (mono) - HEADER_SIZE if you don't support MPEG 2_5 Layer 3, otherwise the maximum here is
 // 2881 if you have a MPEG 2_5 Layer 3 with a bitrate of 160kbps and a sampling rate of 8000Hz.
@@ -10,29 +12,48 @@ use bitstream_io::{BitReader, BigEndian};
 // I'm leaving a bit of wiggle room here just for weird purposes.
 pub const MAX_FRAME_SIZE: u16 = 3000;
 
+// `main_data_begin` is 9 bits, so frames can reach at most 511 bytes back into the reservoir.
+pub const MAX_MAIN_DATA_BEGIN: usize = 511;
+
 #[derive(Debug, Clone)]
 pub struct SideInformation {
     main_data_size: u16, // Size in bytes how long the main data is.
     main_data_begin: u16, // Negative offset to where the audio data begins, ignore static parts of frames.
-    scfsi: [[bool; 4]; 2], // SCaleFactor Selection Information.
+    private_bits: u8, // Kept so the side information can be written back unchanged.
+    scfsi: [[bool; 4]; 2], // SCaleFactor Selection Information, MPEG 1 only.
     granules: [Granule; 2],
+    granule_count: usize, // 2 for MPEG 1, 1 for MPEG 2 and 2.5
+    channel_count: usize,
 }
 
 impl SideInformation {
     pub fn new(header: &Header, data: &[u8]) -> Result<SideInformation, MpError> {
-        let mut reader = BitReader::endian(data, BigEndian);
+        let mut reader = BitReader::new(data);
 
         let mono = header.channel() == &ChannelMode::Mono;
+        // MPEG 2 and 2.5 (LSF) frames have a single granule, a shorter `main_data_begin`, fewer
+        // private bits, no scfsi and a 9 bit `scalefactor_compress` that also encodes preflag.
+        let lsf = header.version() != &Version::Version1;
 
-        let channel_count = if mono { 1 } else { 2 };
-        let private_bits = if mono { 5 } else { 3 };
+        let channel_count = header.channel().channels();
+        let granule_count = if lsf { 1 } else { 2 };
+        let (main_data_begin_bits, private_bits) = match (lsf, mono) {
+            (false, true) => (9, 5),
+            (false, false) => (9, 3),
+            (true, true) => (8, 1),
+            (true, false) => (8, 2),
+        };
+        let intensity_stereo = match *header.channel() {
+            ChannelMode::JointStereo(Extension::Stereo(intensity, _)) => intensity,
+            _ => false,
+        };
 
-        let side_info_size = if mono { 17 } else { 32 };
+        let side_info_size = size(header) as u16;
         // TODO: Check if this should be 0 or -17/-32 - HEADER_SIZE.
         let mut main_data_size = header.frame_size().saturating_sub(side_info_size + ::header::HEADER_SIZE as u16);
         
         if main_data_size > MAX_FRAME_SIZE {
-            return Err(MpError::InvalidData(format!("Frame size too large (>2000): {}", main_data_size)));
+            return Err(ErrorKind::FrameTooLarge(main_data_size).into());
         }
 
         if header.protection() {
@@ -42,87 +63,280 @@ impl SideInformation {
         let mut granules = [Granule::new(); 2];
         let mut scsfi = [[false; 4]; 2];
 
-        let main_data_begin = reader.read(9)?;
+        let main_data_begin = reader.read(main_data_begin_bits)?;
 
-        // Skip private bits
-        reader.skip(private_bits)?;
+        let private: u8 = reader.read(private_bits)?;
 
-        for ch in 0..channel_count {
-            for band in 0..4 {
-                scsfi[ch][band] = reader.read_bit()?;
+        if !lsf {
+            for channel in scsfi.iter_mut().take(channel_count) {
+                for band in channel.iter_mut() {
+                    *band = reader.read_bit()?;
+                }
             }
         }
 
-        for gr in 0..2 {
+        for granule in granules.iter_mut().take(granule_count) {
             for ch in 0..channel_count {
-                granules[gr].part2_3_length[ch] = reader.read(12)?;
-                granules[gr].big_values[ch] = reader.read(9)?;
-                granules[gr].global_gain[ch] = reader.read(8)?;
-                granules[gr].scalefactor_compress[ch] = reader.read(4)?;
-                granules[gr].windows_switching[ch] = reader.read(1)?;
+                granule.part2_3_length[ch] = reader.read(12)?;
+                granule.big_values[ch] = reader.read(9)?;
+                granule.global_gain[ch] = reader.read(8)?;
+                granule.scalefactor_compress[ch] = reader.read(if lsf { 9 } else { 4 })?;
+                granule.windows_switching[ch] = reader.read(1)?;
 
-                if granules[gr].windows_switching[ch] == 1 {
-                    granules[gr].block_type[ch] = reader.read(2)?;
-                    granules[gr].mixed_blockflag[ch] = reader.read_bit()?;
+                if granule.windows_switching[ch] == 1 {
+                    granule.block_type[ch] = BlockType::from(reader.read::<u8>(2)?);
+                    granule.mixed_blockflag[ch] = reader.read_bit()?;
 
                     for region in 0..2 {
-                        granules[gr].table_select[ch][region] = reader.read(5)?;
+                        granule.table_select[ch][region] = reader.read(5)?;
                     }
 
                     for window in 0..3 {
-                        granules[gr].subblock_gain[ch][window] = reader.read(3)?;
+                        granule.subblock_gain[ch][window] = reader.read(3)?;
                     }
 
-                    granules[gr].region0_count[ch] = if granules[gr].block_type[ch] == 2 {
+                    granule.region0_count[ch] = if granule.block_type[ch] == BlockType::Short {
                         8
                     } else {
                         7
                     };
 
                     // Standard is wrong here apparently...
-                    granules[gr].region1_count[ch] = 20 - granules[gr].region0_count[ch];
+                    granule.region1_count[ch] = 20 - granule.region0_count[ch];
                 }
                 else {
                     for region in 0..3 {
-                        granules[gr].table_select[ch][region] = reader.read(5)?;
+                        granule.table_select[ch][region] = reader.read(5)?;
                     }
 
-                    granules[gr].block_type[ch] = 0;
-                    granules[gr].mixed_blockflag[ch] = false;
-                    granules[gr].region0_count[ch] = reader.read(4)?;
-                    granules[gr].region1_count[ch] = reader.read(3)?;
+                    granule.block_type[ch] = BlockType::Normal;
+                    granule.mixed_blockflag[ch] = false;
+                    granule.region0_count[ch] = reader.read(4)?;
+                    granule.region1_count[ch] = reader.read(3)?;
                 }
 
-                granules[gr].preflag[ch] = reader.read_bit()?;
+                granule.preflag[ch] = if lsf {
+                    // The intensity stereo channel uses its own scalefactor_compress table.
+                    granule.scalefactor_compress[ch] >= 500 && !(intensity_stereo && ch == 1)
+                } else {
+                    reader.read_bit()?
+                };
 
-                granules[gr].scalefactor_scale[ch] = reader.read(1)?;
+                granule.scalefactor_scale[ch] = reader.read(1)?;
 
-                granules[gr].count1table_select[ch] = reader.read(1)?;
+                granule.count1table_select[ch] = reader.read(1)?;
             }
         }
         
         Ok(SideInformation {
-            main_data_size: main_data_size,
-            main_data_begin: main_data_begin,
+            main_data_size,
+            main_data_begin,
+            private_bits: private,
             scfsi: scsfi,
-            granules: granules,
+            granules,
+            granule_count,
+            channel_count,
         })
     }
 
+    // Side information of a frame that decodes to silence: every granule is empty and the main
+    // data begins in the frame itself, so its whole main data area is reservoir for later frames.
+    pub fn silent(header: &Header) -> SideInformation {
+        let mut main_data_size = header.frame_size().saturating_sub((size(header) + HEADER_SIZE) as u16);
+        if header.protection() {
+            main_data_size = main_data_size.saturating_sub(CHECKSUM_SIZE as u16);
+        }
+
+        SideInformation {
+            main_data_size,
+            main_data_begin: 0,
+            private_bits: 0,
+            scfsi: [[false; 4]; 2],
+            granules: [Granule::new(); 2],
+            granule_count: if header.version() == &Version::Version1 { 2 } else { 1 },
+            channel_count: header.channel().channels(),
+        }
+    }
+
+    #[inline]
+    pub fn main_data_size(&self) -> u16 {
+        self.main_data_size
+    }
+
+    #[inline]
+    pub fn main_data_begin(&self) -> u16 {
+        self.main_data_begin
+    }
+
+    // Per channel and scalefactor band group, whether granule 1 reuses granule 0's scalefactors.
+    // Always false for MPEG 2 and 2.5.
+    #[inline]
+    pub fn scfsi(&self) -> &[[bool; 4]; 2] {
+        &self.scfsi
+    }
+
+    // The frame's granules, two for MPEG 1 and one for MPEG 2 and 2.5.
+    #[inline]
+    pub fn granules(&self) -> &[Granule] {
+        &self.granules[..self.granule_count]
+    }
+
+    #[inline]
+    pub fn granules_mut(&mut self) -> &mut [Granule] {
+        &mut self.granules[..self.granule_count]
+    }
+
+    // Number of channels with side information in each granule.
+    #[inline]
+    pub fn channels(&self) -> usize {
+        self.channel_count
+    }
+
+    // Bits of main data the granules use for scalefactors and Huffman data. Whatever is left of
+    // the frame's main data after that is reservoir for the following frames.
+    pub fn main_data_bits(&self) -> u32 {
+        self.granules().iter()
+            .flat_map(|granule| granule.part2_3_length[..self.channel_count].iter())
+            .sum()
+    }
+
+    // Size in bytes of this side information, see `size`.
+    pub fn size(&self) -> usize {
+        match (self.granule_count, self.channel_count) {
+            (2, 1) => 17,
+            (2, _) => 32,
+            (_, 1) => 9,
+            (_, _) => 17,
+        }
+    }
+
+    // Writes the side information in its bitstream layout to the start of `data`, returning the
+    // number of bytes written. Fields derived rather than stored (region counts of window
+    // switching granules, MPEG 2 preflag) are not written.
+    pub fn write(&self, data: &mut [u8]) -> Result<usize, MpError> {
+        let length = self.size();
+        if data.len() < length {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        let lsf = self.granule_count == 1;
+        let mono = self.channel_count == 1;
+        let (main_data_begin_bits, private_bits) = match (lsf, mono) {
+            (false, true) => (9, 5),
+            (false, false) => (9, 3),
+            (true, true) => (8, 1),
+            (true, false) => (8, 2),
+        };
+
+        let mut writer = BitWriter::new(&mut data[..length]);
+        writer.write(main_data_begin_bits, self.main_data_begin as u32)?;
+        writer.write(private_bits, self.private_bits as u32)?;
+
+        if !lsf {
+            for channel in self.scfsi.iter().take(self.channel_count) {
+                for &band in channel.iter() {
+                    writer.write_bit(band)?;
+                }
+            }
+        }
+
+        for granule in self.granules() {
+            for ch in 0..self.channel_count {
+                writer.write(12, granule.part2_3_length[ch])?;
+                writer.write(9, granule.big_values[ch])?;
+                writer.write(8, granule.global_gain[ch] as u32)?;
+                writer.write(if lsf { 9 } else { 4 }, granule.scalefactor_compress[ch] as u32)?;
+                writer.write(1, granule.windows_switching[ch] as u32)?;
+
+                if granule.windows_switching[ch] == 1 {
+                    writer.write(2, granule.block_type[ch] as u32)?;
+                    writer.write_bit(granule.mixed_blockflag[ch])?;
+
+                    for region in 0..2 {
+                        writer.write(5, granule.table_select[ch][region])?;
+                    }
+
+                    for window in 0..3 {
+                        writer.write(3, granule.subblock_gain[ch][window])?;
+                    }
+                } else {
+                    for region in 0..3 {
+                        writer.write(5, granule.table_select[ch][region])?;
+                    }
+
+                    writer.write(4, granule.region0_count[ch] as u32)?;
+                    writer.write(3, granule.region1_count[ch] as u32)?;
+                }
+
+                if !lsf {
+                    writer.write_bit(granule.preflag[ch])?;
+                }
+
+                writer.write(1, granule.scalefactor_scale[ch] as u32)?;
+                writer.write(1, granule.count1table_select[ch] as u32)?;
+            }
+        }
+
+        Ok(length)
+    }
+
     // Gets the checksum and checks if the frame is valid.
-    pub fn checksum(data: &[u8]) -> bool {
-        false
+    pub fn checksum(header: &[u8], side_info: &[u8], checksum: u16) -> bool {
+        crc(&header[2..], side_info) == checksum
     }
 }
 
+// Size in bytes of the side information following the header (and checksum) of a frame.
+pub fn size(header: &Header) -> usize {
+    match (header.version(), header.channel()) {
+        (&Version::Version1, &ChannelMode::Mono) => 17,
+        (&Version::Version1, _) => 32,
+        (_, &ChannelMode::Mono) => 9,
+        (_, _) => 17,
+    }
+}
+
+// Writes everything in front of a frame's main data to the start of `data`: the header, the
+// CRC recomputed over the new header and side information if the frame is protected, and the
+// side information. Returns the number of bytes written.
+pub fn write_frame_head(header: &Header, side_information: &SideInformation, data: &mut [u8]) -> Result<usize, MpError> {
+    let mut length = HEADER_SIZE;
+    if header.protection() {
+        length += CHECKSUM_SIZE;
+    }
+    if data.len() < length + side_information.size() {
+        return Err(ErrorKind::EOF.into());
+    }
+
+    let header_bytes = header.to_bytes();
+    data[..HEADER_SIZE].copy_from_slice(&header_bytes);
+    length += side_information.write(&mut data[length..])?;
+    if header.protection() {
+        let checksum = crc(&header_bytes[2..], &data[HEADER_SIZE + CHECKSUM_SIZE..length]);
+        data[HEADER_SIZE..HEADER_SIZE + CHECKSUM_SIZE].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    Ok(length)
+}
+
+// CRC-16 (polynomial 0x8005, initial value 0xFFFF) over the last two header bytes and the side
+// information, as stored after the header of protected frames. This is BUYPASS with the initial
+// value folded into the first two bytes.
+pub fn crc(header: &[u8], side_info: &[u8]) -> u16 {
+    let mut state = State::<BUYPASS>::new();
+    state.update(&[!header[0], !header[1]]);
+    state.update(side_info);
+    state.get()
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Granule {
     part2_3_length: [u32; 2], // Number of bits allocated for scalefactors and Huffman encoded data.
     big_values: [u32; 2],
     global_gain: [u16; 2], // Quantization step size.
-    scalefactor_compress: [u8; 2], // Number of bits used for the transmission of scalefactors.
+    scalefactor_compress: [u16; 2], // Number of bits used for the transmission of scalefactors.
     windows_switching: [u8; 2],
-    block_type: [u8; 2],
+    block_type: [BlockType; 2],
     mixed_blockflag: [bool; 2],
     table_select: [[u32; 3]; 2],
     subblock_gain: [[u32; 3]; 2],
@@ -133,10 +347,46 @@ pub struct Granule {
     count1table_select: [u8; 2],  // Specifies which count1 region Huffman code table applies.
 }
 
+// Window used for a granule's IMDCT. Start and stop blocks are the transitions between normal
+// (long) and short blocks.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub enum BlockType {
+    #[default]
+    Normal,
+    Start,
+    Short,
+    Stop,
+}
+
+impl From<u8> for BlockType {
+    fn from(data: u8) -> BlockType {
+        match data {
+            0 => BlockType::Normal,
+            1 => BlockType::Start,
+            2 => BlockType::Short,
+            3 => BlockType::Stop,
+            _ => unreachable!(),
+        }
+    }
 }
 
 impl Granule {
+    // View of the fields belonging to channel `ch` (0 or 1).
+    #[inline]
+    pub fn channel(&self, ch: usize) -> GranuleChannel<'_> {
+        GranuleChannel {
+            granule: self,
+            ch,
+        }
+    }
+
+    // Sets the quantizer step size of channel `ch`, 8 bits. Changing it by one scales that
+    // channel's output of this granule by 2^(1/4), or 1.5 dB.
+    #[inline]
+    pub fn set_global_gain(&mut self, ch: usize, global_gain: u8) {
+        self.global_gain[ch] = global_gain as u16;
+    }
+
     fn new() -> Granule {
         Granule {
             part2_3_length: [0; 2],
@@ -144,7 +394,7 @@ impl Granule {
             global_gain: [0; 2],
             scalefactor_compress: [0; 2],
             windows_switching: [0; 2],
-            block_type: [0; 2],
+            block_type: [BlockType::Normal; 2],
             mixed_blockflag: [false; 2],
             table_select: [[0; 3]; 2],
             subblock_gain: [[0; 3]; 2],
@@ -156,3 +406,87 @@ impl Granule {
         }
     }
 }
+
+// One channel's side information within a granule.
+#[derive(Debug, Copy, Clone)]
+pub struct GranuleChannel<'a> {
+    granule: &'a Granule,
+    ch: usize,
+}
+
+impl<'a> GranuleChannel<'a> {
+    #[inline]
+    pub fn part2_3_length(&self) -> u32 {
+        self.granule.part2_3_length[self.ch]
+    }
+
+    #[inline]
+    pub fn big_values(&self) -> u32 {
+        self.granule.big_values[self.ch]
+    }
+
+    #[inline]
+    pub fn global_gain(&self) -> u16 {
+        self.granule.global_gain[self.ch]
+    }
+
+    // 4 bits in MPEG 1, 9 bits in MPEG 2 and 2.5.
+    #[inline]
+    pub fn scalefactor_compress(&self) -> u16 {
+        self.granule.scalefactor_compress[self.ch]
+    }
+
+    #[inline]
+    pub fn windows_switching(&self) -> bool {
+        self.granule.windows_switching[self.ch] == 1
+    }
+
+    // Always `Normal` without window switching.
+    #[inline]
+    pub fn block_type(&self) -> BlockType {
+        self.granule.block_type[self.ch]
+    }
+
+    // Whether a short block starts with long block subbands, which are the lowest two.
+    #[inline]
+    pub fn mixed_block(&self) -> bool {
+        self.granule.mixed_blockflag[self.ch]
+    }
+
+    // Huffman tables for the big value regions, the third is unused with window switching.
+    #[inline]
+    pub fn table_select(&self) -> [u32; 3] {
+        self.granule.table_select[self.ch]
+    }
+
+    #[inline]
+    pub fn subblock_gain(&self) -> [u32; 3] {
+        self.granule.subblock_gain[self.ch]
+    }
+
+    #[inline]
+    pub fn region0_count(&self) -> u8 {
+        self.granule.region0_count[self.ch]
+    }
+
+    #[inline]
+    pub fn region1_count(&self) -> u8 {
+        self.granule.region1_count[self.ch]
+    }
+
+    // Read from the side information in MPEG 1, derived from `scalefactor_compress` in MPEG 2.
+    #[inline]
+    pub fn preflag(&self) -> bool {
+        self.granule.preflag[self.ch]
+    }
+
+    #[inline]
+    pub fn scalefactor_scale(&self) -> u8 {
+        self.granule.scalefactor_scale[self.ch]
+    }
+
+    #[inline]
+    pub fn count1table_select(&self) -> u8 {
+        self.granule.count1table_select[self.ch]
+    }
+}
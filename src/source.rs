@@ -0,0 +1,218 @@
+use ::error::{MpError, ErrorKind};
+
+#[cfg(feature = "std")]
+use std::io;
+
+// Minimal byte source the frame reader pulls from. With the `std` feature this is implemented
+// for every `io::Read + io::Seek` and readers that can't seek go through `Unseekable`, otherwise
+// use `SliceSource` or implement it for your storage.
+pub trait ByteSource {
+    // Fills `buf` entirely or errors.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MpError>;
+
+    // Moves to an absolute byte offset from the start of the source.
+    fn seek(&mut self, offset: u64) -> Result<(), MpError>;
+
+    // Current absolute byte offset from the start of the source.
+    fn position(&mut self) -> Result<u64, MpError>;
+
+    // Total length of the source in bytes.
+    fn length(&mut self) -> Result<u64, MpError>;
+
+    fn read_u8(&mut self) -> Result<u8, MpError> {
+        let mut byte = [0u8; 1];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read + io::Seek> ByteSource for R {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MpError> {
+        io::Read::read_exact(self, buf)?;
+        Ok(())
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<(), MpError> {
+        io::Seek::seek(self, io::SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    fn position(&mut self) -> Result<u64, MpError> {
+        Ok(io::Seek::stream_position(self)?)
+    }
+
+    fn length(&mut self) -> Result<u64, MpError> {
+        let position = io::Seek::stream_position(self)?;
+        let length = io::Seek::seek(self, io::SeekFrom::End(0))?;
+        io::Seek::seek(self, io::SeekFrom::Start(position))?;
+        Ok(length)
+    }
+}
+
+// Byte source over a reader that can't seek, such as stdin or a socket. `seek` and `length`
+// fail, so the frame reader treats frames that need backtracking as silent instead.
+#[cfg(feature = "std")]
+pub struct Unseekable<R: io::Read> {
+    inner: R,
+    position: u64,
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read> Unseekable<R> {
+    pub fn new(inner: R) -> Unseekable<R> {
+        Unseekable {
+            inner,
+            position: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read> ByteSource for Unseekable<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MpError> {
+        io::Read::read_exact(&mut self.inner, buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn seek(&mut self, _offset: u64) -> Result<(), MpError> {
+        Err(ErrorKind::NotSeekable.into())
+    }
+
+    fn position(&mut self) -> Result<u64, MpError> {
+        Ok(self.position)
+    }
+
+    fn length(&mut self) -> Result<u64, MpError> {
+        Err(ErrorKind::NotSeekable.into())
+    }
+}
+
+// Files and buffers whose length can be changed, for editing a stream in place when a rewrite
+// shrinks or grows it.
+#[cfg(feature = "std")]
+pub trait SetLen {
+    fn set_len(&mut self, length: u64) -> Result<(), MpError>;
+}
+
+#[cfg(feature = "std")]
+impl SetLen for ::std::fs::File {
+    fn set_len(&mut self, length: u64) -> Result<(), MpError> {
+        ::std::fs::File::set_len(self, length)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl SetLen for io::Cursor<::std::vec::Vec<u8>> {
+    fn set_len(&mut self, length: u64) -> Result<(), MpError> {
+        self.get_mut().resize(length as usize, 0);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: SetLen> SetLen for &mut T {
+    fn set_len(&mut self, length: u64) -> Result<(), MpError> {
+        (**self).set_len(length)
+    }
+}
+
+// Byte source over an in-memory buffer, for when there is no `std::io`.
+pub struct SliceSource<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceSource<'a> {
+    pub fn new(data: &'a [u8]) -> SliceSource<'a> {
+        SliceSource {
+            data,
+            position: 0,
+        }
+    }
+}
+
+impl<'a> ByteSource for SliceSource<'a> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MpError> {
+        let end = self.position + buf.len();
+        if end > self.data.len() {
+            self.position = self.data.len();
+            return Err(ErrorKind::EOF.into());
+        }
+
+        buf.copy_from_slice(&self.data[self.position..end]);
+        self.position = end;
+        Ok(())
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<(), MpError> {
+        if offset > self.data.len() as u64 {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        self.position = offset as usize;
+        Ok(())
+    }
+
+    fn position(&mut self) -> Result<u64, MpError> {
+        Ok(self.position as u64)
+    }
+
+    fn length(&mut self) -> Result<u64, MpError> {
+        Ok(self.data.len() as u64)
+    }
+}
+
+// Byte source that ends at `end`, such as before the tags at the end of a file.
+pub struct Bounded<'a, R: 'a> {
+    inner: &'a mut R,
+    position: u64,
+    end: u64,
+}
+
+impl<'a, R: ByteSource> Bounded<'a, R> {
+    pub fn new(inner: &'a mut R, end: u64) -> Result<Bounded<'a, R>, MpError> {
+        let position = inner.position()?;
+        Ok(Bounded {
+            inner,
+            position,
+            end,
+        })
+    }
+}
+
+impl<'a, R: ByteSource> ByteSource for Bounded<'a, R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MpError> {
+        if self.position + buf.len() as u64 > self.end {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        self.inner.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<(), MpError> {
+        if offset > self.end {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        self.inner.seek(offset)?;
+        self.position = offset;
+        Ok(())
+    }
+
+    fn position(&mut self) -> Result<u64, MpError> {
+        Ok(self.position)
+    }
+
+    fn length(&mut self) -> Result<u64, MpError> {
+        Ok(self.end)
+    }
+}
@@ -81,3 +81,15 @@ pub static SCALE_COMPRESS: [(u8, u8); 16] = [
     (4, 2),
     (4, 3),
 ];
+
+// MPEG 2 and 2.5 scalefactors per partition (ISO 13818-3 table B.6), by the slen table that
+// `scalefactor_compress` selects, then long, short and mixed blocks. Short block counts are in
+// scalefactors rather than bands, three per band. The first 6 of a mixed block are long bands.
+pub static NR_OF_SFB: [[[u8; 4]; 3]; 6] = [
+    [[6, 5, 5, 5], [9, 9, 9, 9], [6, 9, 9, 9]],
+    [[6, 5, 7, 3], [9, 9, 12, 6], [6, 9, 12, 6]],
+    [[11, 10, 0, 0], [18, 18, 0, 0], [15, 18, 0, 0]],
+    [[7, 7, 7, 0], [12, 12, 12, 0], [6, 15, 12, 0]],
+    [[6, 6, 6, 3], [12, 9, 9, 6], [6, 12, 9, 6]],
+    [[8, 8, 5, 0], [15, 12, 9, 0], [6, 18, 9, 0]],
+];
@@ -0,0 +1,119 @@
+use core::ops::Range;
+
+use ::error::MpError;
+use ::source::ByteSource;
+
+// ID3v2 headers and footers are 10 bytes.
+pub const ID3V2_HEADER_SIZE: u64 = 10;
+// ID3v1 tags are always the last 128 bytes of the file.
+pub const ID3V1_SIZE: u64 = 128;
+// APEv2 headers and footers are 32 bytes.
+pub const APE_FOOTER_SIZE: u64 = 32;
+
+// Where the metadata tags around the audio frames are. Only their location is read, not their
+// contents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tags {
+    id3v2: Option<Range<u64>>,
+    id3v2_version: u8, // Major version, 3 for ID3v2.3 and 4 for ID3v2.4
+    ape: Option<Range<u64>>,
+    id3v1: Option<Range<u64>>,
+    audio: Range<u64>, // Everything between the leading and trailing tags
+}
+
+impl Tags {
+    // Finds an ID3v2 tag at the start, and APEv2 and ID3v1 tags at the end of the source. The
+    // reader is left at the start of the audio.
+    pub fn locate<R: ByteSource>(reader: &mut R) -> Result<Tags, MpError> {
+        let length = reader.length()?;
+        let mut tags = Tags {
+            audio: 0..length,
+            ..Tags::default()
+        };
+
+        // Some encoders write several ID3v2 tags back to back, the audio starts after the last.
+        loop {
+            let start = tags.audio.start;
+            if tags.audio.end - start < ID3V2_HEADER_SIZE {
+                break;
+            }
+
+            let mut header = [0u8; ID3V2_HEADER_SIZE as usize];
+            reader.seek(start)?;
+            reader.read_exact(&mut header)?;
+            if &header[0..3] != b"ID3" || header[6..10].iter().any(|byte| byte & 0x80 != 0) {
+                break;
+            }
+
+            let mut size = ID3V2_HEADER_SIZE + synchsafe(&header[6..10]) as u64;
+            if header[5] & 0x10 != 0 {
+                size += ID3V2_HEADER_SIZE; // footer
+            }
+            let end = (start + size).min(tags.audio.end);
+            if tags.id3v2.is_none() {
+                tags.id3v2 = Some(start..end);
+                tags.id3v2_version = header[3];
+            }
+            tags.audio.start = end;
+        }
+
+        if tags.audio.end - tags.audio.start >= ID3V1_SIZE {
+            let mut marker = [0u8; 3];
+            reader.seek(tags.audio.end - ID3V1_SIZE)?;
+            reader.read_exact(&mut marker)?;
+            if &marker == b"TAG" {
+                tags.id3v1 = Some(tags.audio.end - ID3V1_SIZE..tags.audio.end);
+                tags.audio.end -= ID3V1_SIZE;
+            }
+        }
+
+        if tags.audio.end - tags.audio.start >= APE_FOOTER_SIZE {
+            let mut footer = [0u8; APE_FOOTER_SIZE as usize];
+            reader.seek(tags.audio.end - APE_FOOTER_SIZE)?;
+            reader.read_exact(&mut footer)?;
+            if &footer[0..8] == b"APETAGEX" {
+                // Size covers the items and footer, the header is flagged separately.
+                let mut size = u32::from_le_bytes([footer[12], footer[13], footer[14], footer[15]]) as u64;
+                if footer[23] & 0x80 != 0 {
+                    size += APE_FOOTER_SIZE;
+                }
+                let start = tags.audio.end.saturating_sub(size).max(tags.audio.start);
+                tags.ape = Some(start..tags.audio.end);
+                tags.audio.end = start;
+            }
+        }
+
+        reader.seek(tags.audio.start)?;
+        Ok(tags)
+    }
+
+    #[inline]
+    pub fn id3v2(&self) -> Option<Range<u64>> {
+        self.id3v2.clone()
+    }
+
+    #[inline]
+    pub fn id3v2_version(&self) -> u8 {
+        self.id3v2_version
+    }
+
+    #[inline]
+    pub fn ape(&self) -> Option<Range<u64>> {
+        self.ape.clone()
+    }
+
+    #[inline]
+    pub fn id3v1(&self) -> Option<Range<u64>> {
+        self.id3v1.clone()
+    }
+
+    #[inline]
+    pub fn audio(&self) -> Range<u64> {
+        self.audio.clone()
+    }
+}
+
+// Decodes a 28 bit integer stored 7 bits per byte, as ID3v2 sizes are.
+pub fn synchsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |size, byte| (size << 7) | (byte & 0x7F) as u32)
+}
@@ -0,0 +1,162 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::{self, Display, Formatter};
+
+use ::decoder::{FrameReader, ReservoirPolicy};
+use ::diagnostics::Event;
+use ::error::{MpError, ErrorKind};
+use ::header::ChannelMode;
+use ::probe::{self, Scan};
+use ::source::{Bounded, ByteSource};
+
+// Something wrong with a stream, found by `validate`. Offsets are absolute byte offsets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    // Bytes skipped between frames (or before the first one) to find the next header.
+    Junk { offset: u64, length: u64 },
+    // No header found within the search limit, the region was given up on.
+    LostSync { offset: u64, length: u64 },
+    SamplingRateChange { offset: u64, from: u16, to: u16 },
+    ChannelModeChange { offset: u64, from: ChannelMode, to: ChannelMode },
+    BadCrc { offset: u64, expected: u16, actual: u16 },
+    // `main_data_begin` reaches back further than the main data of the frames before it.
+    ReservoirViolation { offset: u64, needed: u16, available: usize },
+    // The last frame is cut short by the end of the stream (or the tags after it).
+    TruncatedFrame { offset: u64 },
+    // The Xing/VBRI header's frame count doesn't match the frames in the stream.
+    FrameCountMismatch { declared: u32, actual: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    frames: u64, // Frames read, including a VBR header frame
+    problems: Vec<Problem>,
+}
+
+impl Report {
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    #[inline]
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// Walks every frame between the tags and reports everything that looks wrong. Only errors from
+// the source itself, or a stream without any frames, are returned as `Err`.
+pub fn validate<R: ByteSource>(reader: &mut R) -> Result<Report, MpError> {
+    let info = probe::probe(reader, Scan::Bitrate)?;
+    let audio = info.tags().audio();
+    reader.seek(audio.start)?;
+
+    let problems = Rc::new(RefCell::new(Vec::new()));
+    let mut frame_reader = FrameReader::new(Bounded::new(reader, audio.end)?);
+    // Backtracking would hide reservoir violations.
+    frame_reader.set_reservoir_policy(ReservoirPolicy::Silence);
+    let sink = problems.clone();
+    frame_reader.set_diagnostics(move |event: &Event| {
+        let problem = match *event {
+            Event::Skipped { offset, length } => Problem::Junk { offset, length },
+            Event::CrcMismatch { offset, expected, actual } => Problem::BadCrc { offset, expected, actual },
+            Event::ReservoirUnderflow { offset, needed, available } => Problem::ReservoirViolation { offset, needed, available },
+            Event::FormatChanged { offset, ref from, ref to } => {
+                let mut problems = sink.borrow_mut();
+                if from.sampling_rate() != to.sampling_rate() {
+                    problems.push(Problem::SamplingRateChange {
+                        offset,
+                        from: from.sampling_rate(),
+                        to: to.sampling_rate(),
+                    });
+                }
+                if !from.channel().same_layout(to.channel()) {
+                    problems.push(Problem::ChannelModeChange {
+                        offset,
+                        from: from.channel().clone(),
+                        to: to.channel().clone(),
+                    });
+                }
+                return;
+            },
+            Event::SyncLost { .. } | Event::BadHeader { .. } => return,
+        };
+        sink.borrow_mut().push(problem);
+    });
+
+    let mut frames: u64 = 0;
+    loop {
+        match frame_reader.advance() {
+            Ok(_) => (),
+            Err(err) => match *err.kind() {
+                ErrorKind::EOF => break,
+                ErrorKind::TruncatedFrame => {
+                    let offset = err.location().map_or(audio.end, |location| location.offset);
+                    problems.borrow_mut().push(Problem::TruncatedFrame { offset });
+                    break;
+                },
+                ErrorKind::SyncLost => {
+                    let mut problems = problems.borrow_mut();
+                    if let Some(Problem::Junk { offset, length }) = problems.last().cloned() {
+                        problems.pop();
+                        problems.push(Problem::LostSync { offset, length });
+                    }
+                    continue;
+                },
+                ErrorKind::UnsupportedLayer(_) => {
+                    frames += 1;
+                    continue;
+                },
+                _ if err.is_recoverable() => continue,
+                _ => return Err(err),
+            },
+        }
+        frames += 1;
+    }
+
+    if let Some(declared) = info.vbr_header().and_then(|vbr_header| vbr_header.frames()) {
+        let actual = frames.saturating_sub(1);
+        if declared as u64 != actual {
+            problems.borrow_mut().push(Problem::FrameCountMismatch { declared, actual });
+        }
+    }
+
+    drop(frame_reader);
+    let problems = problems.borrow().clone();
+    Ok(Report {
+        frames,
+        problems,
+    })
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Problem::Junk { offset, length } => write!(f, "{}: {} bytes of junk", offset, length),
+            Problem::LostSync { offset, length } => write!(f, "{}: lost sync for {} bytes", offset, length),
+            Problem::SamplingRateChange { offset, from, to } => write!(
+                f, "{}: sampling rate changes from {} Hz to {} Hz", offset, from, to,
+            ),
+            Problem::ChannelModeChange { offset, from, to } => write!(
+                f, "{}: channel mode changes from {:?} to {:?}", offset, from, to,
+            ),
+            Problem::BadCrc { offset, expected, actual } => write!(
+                f, "{}: CRC mismatch, stored {:04x} but computed {:04x}", offset, expected, actual,
+            ),
+            Problem::ReservoirViolation { offset, needed, available } => write!(
+                f, "{}: main data begins {} bytes back but only {} are available", offset, needed, available,
+            ),
+            Problem::TruncatedFrame { offset } => write!(f, "{}: last frame is truncated", offset),
+            Problem::FrameCountMismatch { declared, actual } => write!(
+                f, "VBR header declares {} frames but the stream has {}", declared, actual,
+            ),
+        }
+    }
+}
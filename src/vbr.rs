@@ -0,0 +1,617 @@
+use crc16::{State, ARC};
+
+use ::error::{MpError, ErrorKind};
+use ::header::{Header, HEADER_SIZE};
+use ::side_info;
+
+// Xing headers carry a 100 entry seek table.
+pub const XING_TOC_SIZE: usize = 100;
+// The LAME extension following the Xing header is 36 bytes.
+pub const LAME_SIZE: usize = 36;
+
+// Encoder delay and padding are 12 bits each in the LAME header.
+pub const MAX_GAPLESS: u16 = 4095;
+
+// Largest believable peak amplitude, decoders overshoot full scale but not by this much.
+const MAX_PEAK: f32 = 8.0;
+
+// Xing flags for which optional fields are present.
+pub const XING_FRAMES: u32 = 0x1;
+pub const XING_BYTES: u32 = 0x2;
+pub const XING_TOC: u32 = 0x4;
+pub const XING_QUALITY: u32 = 0x8;
+
+// How the encoder spread bits over the frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BitrateMode {
+    Constant,
+    Average, // ABR, varies around a target bitrate
+    Variable,
+}
+
+// Metadata some encoders put in the first frame of the stream, in place of audio.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VbrHeader {
+    Xing(XingHeader),
+    Vbri(VbriHeader),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct XingHeader {
+    info: bool, // Tagged "Info" instead of "Xing", written for CBR streams
+    frames: Option<u32>, // Number of frames, not counting the one holding this header
+    bytes: Option<u32>, // Number of bytes, including this frame
+    toc: Option<[u8; XING_TOC_SIZE]>, // Byte positions (in 1/256 of `bytes`) at each percent of the duration
+    quality: Option<u32>,
+    lame: Option<LameHeader>,
+}
+
+// LAME (and FFmpeg) extension of the Xing header.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LameHeader {
+    encoder: [u8; 9], // Encoder name and version, such as "LAME3.99r"
+    revision: u8,
+    vbr_method: u8,
+    lowpass: u8, // Lowpass frequency in 100Hz
+    replay_gain: [u8; 8], // Peak amplitude, radio and audiophile gain
+    flags: u8, // Encoding flags and ATH type
+    bitrate: u8, // ABR target or minimal VBR bitrate (kbps)
+    encoder_delay: u16, // Samples added at the start by the encoder
+    padding: u16, // Samples added at the end
+    misc: u8,
+    mp3_gain: u8,
+    preset: u16, // Surround info and encoding preset
+    music_length: u32, // Bytes from this frame to the last audio frame
+    music_crc: u16,
+    crc: u16, // CRC-16 of the first 190 bytes of this frame
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VbriHeader {
+    version: u16,
+    delay: u16,
+    quality: u16,
+    bytes: u32,
+    frames: u32,
+}
+
+impl VbrHeader {
+    // Looks for a Xing/Info or VBRI header in a whole frame, header included.
+    pub fn parse(header: &Header, frame: &[u8]) -> Option<VbrHeader> {
+        if let Some(xing) = XingHeader::parse(header, frame) {
+            return Some(VbrHeader::Xing(xing));
+        }
+
+        VbriHeader::parse(frame).map(VbrHeader::Vbri)
+    }
+
+    // Number of audio frames, excluding the one holding this header.
+    pub fn frames(&self) -> Option<u32> {
+        match self {
+            VbrHeader::Xing(xing) => xing.frames,
+            VbrHeader::Vbri(vbri) => Some(vbri.frames),
+        }
+    }
+
+    pub fn bytes(&self) -> Option<u32> {
+        match self {
+            VbrHeader::Xing(xing) => xing.bytes,
+            VbrHeader::Vbri(vbri) => Some(vbri.bytes),
+        }
+    }
+
+    // Bitrate mode as declared by the encoder.
+    pub fn bitrate_mode(&self) -> BitrateMode {
+        match self {
+            VbrHeader::Xing(xing) => match xing.lame.as_ref().and_then(|lame| lame.bitrate_mode()) {
+                Some(mode) => mode,
+                None if xing.info => BitrateMode::Constant,
+                None => BitrateMode::Variable,
+            },
+            VbrHeader::Vbri(_) => BitrateMode::Variable,
+        }
+    }
+}
+
+impl XingHeader {
+    pub fn parse(header: &Header, frame: &[u8]) -> Option<XingHeader> {
+        let mut position = xing_offset(header);
+        let tag = frame.get(position..position + 4)?;
+        let info = match tag {
+            b"Xing" => false,
+            b"Info" => true,
+            _ => return None,
+        };
+
+        let flags = read_u32(frame, position + 4)?;
+        position += 8;
+
+        let mut xing = XingHeader {
+            info,
+            frames: None,
+            bytes: None,
+            toc: None,
+            quality: None,
+            lame: None,
+        };
+
+        if flags & XING_FRAMES != 0 {
+            xing.frames = Some(read_u32(frame, position)?);
+            position += 4;
+        }
+
+        if flags & XING_BYTES != 0 {
+            xing.bytes = Some(read_u32(frame, position)?);
+            position += 4;
+        }
+
+        if flags & XING_TOC != 0 {
+            let mut toc = [0u8; XING_TOC_SIZE];
+            toc.copy_from_slice(frame.get(position..position + XING_TOC_SIZE)?);
+            xing.toc = Some(toc);
+            position += XING_TOC_SIZE;
+        }
+
+        if flags & XING_QUALITY != 0 {
+            xing.quality = Some(read_u32(frame, position)?);
+            position += 4;
+        }
+
+        xing.lame = frame.get(position..position + LAME_SIZE).and_then(LameHeader::parse);
+        Some(xing)
+    }
+
+    // A header for `frames` audio frames in `bytes` bytes, the frame holding it included. Tagged
+    // "Info" when `info` is set, which is what LAME writes for CBR streams.
+    pub fn new(info: bool, frames: u32, bytes: u32, toc: [u8; XING_TOC_SIZE]) -> XingHeader {
+        XingHeader {
+            info,
+            frames: Some(frames),
+            bytes: Some(bytes),
+            toc: Some(toc),
+            quality: None,
+            lame: None,
+        }
+    }
+
+    pub fn set_lame(&mut self, lame: Option<LameHeader>) {
+        self.lame = lame;
+    }
+
+    // Bytes from the start of a frame with this header to the end of the Xing header and its
+    // LAME extension.
+    pub fn size(&self, header: &Header) -> usize {
+        let mut size = xing_offset(header) + 8;
+        size += self.frames.map_or(0, |_| 4);
+        size += self.bytes.map_or(0, |_| 4);
+        size += self.toc.map_or(0, |_| XING_TOC_SIZE);
+        size += self.written_quality().map_or(0, |_| 4);
+        size += self.lame.map_or(0, |_| LAME_SIZE);
+        size
+    }
+
+    // Header for the frame holding this Xing header, in the format of `first`, the stream's first
+    // audio frame. The frame gets no CRC or padding. Info frames keep the stream's bitrate if it is
+    // large enough so the stream stays CBR, otherwise the lowest bitrate that fits is used.
+    pub fn frame_header(&self, first: &Header) -> Result<Header, MpError> {
+        let mut bytes = first.to_bytes();
+        bytes[1] |= 0b0000_0001;
+        bytes[2] &= !0b0000_0010;
+        let header = Header::new(&bytes)?;
+        if self.info && header.frame_size() as usize >= self.size(&header) {
+            return Ok(header);
+        }
+
+        for index in 1..15 {
+            bytes[2] = (bytes[2] & 0b0000_1111) | index << 4;
+            if let Ok(candidate) = Header::new(&bytes) {
+                if candidate.frame_size() as usize >= self.size(&candidate) {
+                    return Ok(candidate);
+                }
+            }
+        }
+
+        Err(ErrorKind::FrameTooSmall(header.frame_size()).into())
+    }
+
+    // Writes a whole frame holding this header to the start of `frame` and returns its size. The
+    // side information is left zeroed, so decoders that don't know about Xing headers play the
+    // frame as silence. `header` should come from `frame_header`, the Xing header position
+    // doesn't account for a CRC.
+    pub fn write(&self, header: &Header, frame: &mut [u8]) -> Result<usize, MpError> {
+        let frame_size = header.frame_size() as usize;
+        if frame_size < self.size(header) {
+            return Err(ErrorKind::FrameTooSmall(header.frame_size()).into());
+        }
+        if frame.len() < frame_size {
+            return Err(ErrorKind::EOF.into());
+        }
+
+        let frame = &mut frame[..frame_size];
+        for byte in frame.iter_mut() {
+            *byte = 0;
+        }
+        frame[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+
+        let tag = xing_offset(header);
+        frame[tag..tag + 4].copy_from_slice(if self.info { b"Info" } else { b"Xing" });
+        let mut flags = 0;
+        let mut position = tag + 8;
+        if let Some(frames) = self.frames {
+            flags |= XING_FRAMES;
+            frame[position..position + 4].copy_from_slice(&frames.to_be_bytes());
+            position += 4;
+        }
+        if let Some(bytes) = self.bytes {
+            flags |= XING_BYTES;
+            frame[position..position + 4].copy_from_slice(&bytes.to_be_bytes());
+            position += 4;
+        }
+        if let Some(ref toc) = self.toc {
+            flags |= XING_TOC;
+            frame[position..position + XING_TOC_SIZE].copy_from_slice(toc);
+            position += XING_TOC_SIZE;
+        }
+        if let Some(quality) = self.written_quality() {
+            flags |= XING_QUALITY;
+            frame[position..position + 4].copy_from_slice(&quality.to_be_bytes());
+            position += 4;
+        }
+        frame[tag + 4..tag + 8].copy_from_slice(&flags.to_be_bytes());
+
+        if let Some(ref lame) = self.lame {
+            lame.write(&mut frame[position..position + LAME_SIZE]);
+            // The LAME CRC covers the frame up to the CRC itself.
+            let crc = State::<ARC>::calculate(&frame[..position + LAME_SIZE - 2]);
+            frame[position + LAME_SIZE - 2..position + LAME_SIZE].copy_from_slice(&crc.to_be_bytes());
+        }
+
+        Ok(frame_size)
+    }
+
+    #[inline]
+    pub fn info(&self) -> bool {
+        self.info
+    }
+
+    #[inline]
+    pub fn frames(&self) -> Option<u32> {
+        self.frames
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> Option<u32> {
+        self.bytes
+    }
+
+    #[inline]
+    pub fn toc(&self) -> Option<&[u8; XING_TOC_SIZE]> {
+        self.toc.as_ref()
+    }
+
+    #[inline]
+    pub fn quality(&self) -> Option<u32> {
+        self.quality
+    }
+
+    #[inline]
+    pub fn lame(&self) -> Option<&LameHeader> {
+        self.lame.as_ref()
+    }
+
+    // LAME writes every Xing field and readers expect its header right after them, so the quality
+    // field is written in front of one even if it wasn't set.
+    fn written_quality(&self) -> Option<u32> {
+        match self.lame {
+            Some(_) => Some(self.quality.unwrap_or(0)),
+            None => self.quality,
+        }
+    }
+}
+
+impl LameHeader {
+    // An extension holding only the bitrate mode and gapless playback information. Decoders only
+    // read the delay and padding behind an encoder tag they know, so it is tagged as LAME.
+    pub fn new(bitrate_mode: BitrateMode, encoder_delay: u16, padding: u16) -> LameHeader {
+        LameHeader {
+            encoder: *b"LAME\0\0\0\0\0",
+            revision: 0,
+            vbr_method: vbr_method(bitrate_mode),
+            lowpass: 0,
+            replay_gain: [0; 8],
+            flags: 0,
+            bitrate: 0,
+            encoder_delay: encoder_delay.min(MAX_GAPLESS),
+            padding: padding.min(MAX_GAPLESS),
+            misc: 0,
+            mp3_gain: 0,
+            preset: 0,
+            music_length: 0,
+            music_crc: 0,
+            crc: 0,
+        }
+    }
+
+    // Parses the 36 bytes following the Xing fields, if they were written by LAME or FFmpeg.
+    pub fn parse(data: &[u8]) -> Option<LameHeader> {
+        if data.len() < LAME_SIZE {
+            return None;
+        }
+
+        match &data[0..4] {
+            b"LAME" | b"Lavf" | b"Lavc" => (),
+            _ => return None,
+        }
+
+        let mut encoder = [0u8; 9];
+        encoder.copy_from_slice(&data[0..9]);
+        let mut replay_gain = [0u8; 8];
+        replay_gain.copy_from_slice(&data[11..19]);
+
+        Some(LameHeader {
+            encoder,
+            revision: data[9] >> 4,
+            vbr_method: data[9] & 0x0F,
+            lowpass: data[10],
+            replay_gain,
+            flags: data[19],
+            bitrate: data[20],
+            encoder_delay: ((data[21] as u16) << 4) | (data[22] as u16 >> 4),
+            padding: ((data[22] as u16 & 0x0F) << 8) | data[23] as u16,
+            misc: data[24],
+            mp3_gain: data[25],
+            preset: u16::from_be_bytes([data[26], data[27]]),
+            music_length: read_u32(data, 28)?,
+            music_crc: u16::from_be_bytes([data[32], data[33]]),
+            crc: u16::from_be_bytes([data[34], data[35]]),
+        })
+    }
+
+    // Bitrate mode from the VBR method field, `None` if the encoder left it unknown.
+    pub fn bitrate_mode(&self) -> Option<BitrateMode> {
+        match self.vbr_method {
+            1 | 8 => Some(BitrateMode::Constant),
+            2 | 9 => Some(BitrateMode::Average),
+            3..=6 => Some(BitrateMode::Variable),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn encoder(&self) -> &[u8; 9] {
+        &self.encoder
+    }
+
+    #[inline]
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    #[inline]
+    pub fn vbr_method(&self) -> u8 {
+        self.vbr_method
+    }
+
+    // Sets the VBR method to the plain one for `bitrate_mode`.
+    pub fn set_bitrate_mode(&mut self, bitrate_mode: BitrateMode) {
+        self.vbr_method = vbr_method(bitrate_mode);
+    }
+
+    #[inline]
+    pub fn lowpass(&self) -> u8 {
+        self.lowpass
+    }
+
+    #[inline]
+    pub fn replay_gain(&self) -> &[u8; 8] {
+        &self.replay_gain
+    }
+
+    // Peak sample amplitude of the encoded audio (1.0 is full scale), if the encoder stored it.
+    // LAME writes it as 8.23 fixed point, despite the documentation calling it a float. Old FFmpeg
+    // versions let their encoder string run into this field, so implausible values are ignored.
+    pub fn peak(&self) -> Option<f32> {
+        let peak = u32::from_be_bytes([self.replay_gain[0], self.replay_gain[1], self.replay_gain[2], self.replay_gain[3]]);
+        let peak = peak as f32 / (1 << 23) as f32;
+        if peak == 0.0 || peak > MAX_PEAK {
+            None
+        } else {
+            Some(peak)
+        }
+    }
+
+    // Zeroes the peak, the ReplayGain values and the MP3Gain change, which only describe the audio
+    // the encoder wrote.
+    pub fn clear_replay_gain(&mut self) {
+        self.replay_gain = [0; 8];
+        self.mp3_gain = 0;
+    }
+
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    #[inline]
+    pub fn bitrate(&self) -> u8 {
+        self.bitrate
+    }
+
+    #[inline]
+    pub fn encoder_delay(&self) -> u16 {
+        self.encoder_delay
+    }
+
+    // Clamped to `MAX_GAPLESS`, as is `set_padding`.
+    pub fn set_encoder_delay(&mut self, encoder_delay: u16) {
+        self.encoder_delay = encoder_delay.min(MAX_GAPLESS);
+    }
+
+    pub fn set_padding(&mut self, padding: u16) {
+        self.padding = padding.min(MAX_GAPLESS);
+    }
+
+    // Length of the stream from the Xing frame to the end of the last audio frame, and the
+    // CRC-16 (ARC) of the audio frames.
+    pub fn set_music(&mut self, length: u32, crc: u16) {
+        self.music_length = length;
+        self.music_crc = crc;
+    }
+
+    #[inline]
+    pub fn padding(&self) -> u16 {
+        self.padding
+    }
+
+    #[inline]
+    pub fn misc(&self) -> u8 {
+        self.misc
+    }
+
+    #[inline]
+    pub fn mp3_gain(&self) -> u8 {
+        self.mp3_gain
+    }
+
+    #[inline]
+    pub fn preset(&self) -> u16 {
+        self.preset
+    }
+
+    #[inline]
+    pub fn music_length(&self) -> u32 {
+        self.music_length
+    }
+
+    #[inline]
+    pub fn music_crc(&self) -> u16 {
+        self.music_crc
+    }
+
+    #[inline]
+    pub fn crc(&self) -> u16 {
+        self.crc
+    }
+}
+
+impl LameHeader {
+    // Writes the 36 byte layout `parse` reads. The CRC is written as stored, `XingHeader::write`
+    // replaces it.
+    fn write(&self, data: &mut [u8]) {
+        data[0..9].copy_from_slice(&self.encoder);
+        data[9] = self.revision << 4 | self.vbr_method & 0x0F;
+        data[10] = self.lowpass;
+        data[11..19].copy_from_slice(&self.replay_gain);
+        data[19] = self.flags;
+        data[20] = self.bitrate;
+        data[21] = (self.encoder_delay >> 4) as u8;
+        data[22] = ((self.encoder_delay & 0x0F) << 4) as u8 | (self.padding >> 8) as u8;
+        data[23] = self.padding as u8;
+        data[24] = self.misc;
+        data[25] = self.mp3_gain;
+        data[26..28].copy_from_slice(&self.preset.to_be_bytes());
+        data[28..32].copy_from_slice(&self.music_length.to_be_bytes());
+        data[32..34].copy_from_slice(&self.music_crc.to_be_bytes());
+        data[34..36].copy_from_slice(&self.crc.to_be_bytes());
+    }
+}
+
+impl VbriHeader {
+    // VBRI headers (Fraunhofer) always start 32 bytes after the frame header.
+    pub fn parse(frame: &[u8]) -> Option<VbriHeader> {
+        let position = HEADER_SIZE + 32;
+        if frame.get(position..position + 4)? != b"VBRI" {
+            return None;
+        }
+
+        let read_u16 = |offset: usize| -> Option<u16> {
+            let bytes = frame.get(position + offset..position + offset + 2)?;
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+
+        Some(VbriHeader {
+            version: read_u16(4)?,
+            delay: read_u16(6)?,
+            quality: read_u16(8)?,
+            bytes: read_u32(frame, position + 10)?,
+            frames: read_u32(frame, position + 14)?,
+        })
+    }
+
+    #[inline]
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    #[inline]
+    pub fn delay(&self) -> u16 {
+        self.delay
+    }
+
+    #[inline]
+    pub fn quality(&self) -> u16 {
+        self.quality
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> u32 {
+        self.bytes
+    }
+
+    #[inline]
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+// Xing headers go where the side information would end, which depends on version and channels.
+pub fn xing_offset(header: &Header) -> usize {
+    HEADER_SIZE + side_info::size(header)
+}
+
+// Offset of the LAME header in a whole frame holding a Xing header, after the Xing fields its
+// flags say are present. `XingHeader::size` can't tell, it describes the layout `write` uses.
+pub fn lame_offset(header: &Header, frame: &[u8]) -> Option<usize> {
+    let tag = xing_offset(header);
+    match frame.get(tag..tag + 4)? {
+        b"Xing" | b"Info" => (),
+        _ => return None,
+    }
+
+    let flags = read_u32(frame, tag + 4)?;
+    let mut position = tag + 8;
+    position += if flags & XING_FRAMES != 0 { 4 } else { 0 };
+    position += if flags & XING_BYTES != 0 { 4 } else { 0 };
+    position += if flags & XING_TOC != 0 { XING_TOC_SIZE } else { 0 };
+    position += if flags & XING_QUALITY != 0 { 4 } else { 0 };
+    frame.get(position..position + LAME_SIZE).and_then(LameHeader::parse).map(|_| position)
+}
+
+// Xing seek table from the byte positions of every audio frame, counted from the start of the
+// Xing frame, in a stream of `bytes` bytes. Entry i is the position of the frame i% of the way
+// through, in 1/256 of the stream.
+pub fn toc(positions: &[u64], bytes: u64) -> [u8; XING_TOC_SIZE] {
+    let mut toc = [0u8; XING_TOC_SIZE];
+    if positions.is_empty() || bytes == 0 {
+        return toc;
+    }
+
+    for (i, entry) in toc.iter_mut().enumerate() {
+        let position = positions[i * positions.len() / XING_TOC_SIZE];
+        *entry = (position * 256 / bytes).min(255) as u8;
+    }
+    toc
+}
+
+fn vbr_method(bitrate_mode: BitrateMode) -> u8 {
+    match bitrate_mode {
+        BitrateMode::Constant => 1,
+        BitrateMode::Average => 2,
+        BitrateMode::Variable => 4,
+    }
+}
+
+fn read_u32(data: &[u8], position: usize) -> Option<u32> {
+    let bytes = data.get(position..position + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
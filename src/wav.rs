@@ -0,0 +1,272 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use ::error::{MpError, ErrorKind};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Tail of the KSDATAFORMAT_SUBTYPE GUIDs, the first two bytes are the format tag.
+const SUBTYPE_GUID: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+// Sizes of the "fmt " chunk body.
+const FMT_SIZE: u32 = 16;
+const FMT_EXTENSIBLE_SIZE: u32 = 40;
+
+// How samples are stored in the data chunk.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SampleFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl SampleFormat {
+    #[inline]
+    pub fn bits(&self) -> u16 {
+        match *self {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Float32 => 32,
+        }
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> u16 {
+        self.bits() / 8
+    }
+
+    fn tag(&self) -> u16 {
+        match *self {
+            SampleFormat::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WavSpec {
+    channels: u16,
+    sampling_rate: u32, // Hz
+    sample_format: SampleFormat,
+}
+
+impl WavSpec {
+    pub fn new(channels: u16, sampling_rate: u32, sample_format: SampleFormat) -> WavSpec {
+        WavSpec {
+            channels,
+            sampling_rate,
+            sample_format,
+        }
+    }
+
+    #[inline]
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[inline]
+    pub fn sampling_rate(&self) -> u32 {
+        self.sampling_rate
+    }
+
+    #[inline]
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    // Bytes per sample frame (one sample for every channel).
+    #[inline]
+    pub fn block_align(&self) -> u16 {
+        self.channels * self.sample_format.bytes()
+    }
+
+    // More than two channels need WAVE_FORMAT_EXTENSIBLE to carry a speaker layout, and integer
+    // samples of more than 16 bits need it to state their valid bits.
+    #[inline]
+    pub fn extensible(&self) -> bool {
+        self.channels > 2 || (self.sample_format != SampleFormat::Float32 && self.sample_format.bits() > 16)
+    }
+}
+
+// Writes interleaved samples to a RIFF/WAVE file. The chunk sizes are placeholders until
+// `finish` seeks back and fills them in, so a writer that is dropped leaves a file that claims
+// to be empty.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    spec: WavSpec,
+
+    // Absolute offsets of the fields fixed up by `finish`.
+    riff_size: u64,
+    fact_length: Option<u64>, // Only float files have a fact chunk
+    data_size: u64,
+
+    // Bytes of sample data written so far.
+    written: u64,
+
+    // Largest RIFF size `write_sample` allows.
+    max_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    // Writes the RIFF header, format chunk and the start of the data chunk. Errors with
+    // `BadWavSpec` for a spec without channels or sampling rate, or one whose block align or byte
+    // rate don't fit their fields.
+    pub fn new(mut writer: W, spec: WavSpec) -> Result<WavWriter<W>, MpError> {
+        let block_align = spec.channels as u32 * spec.sample_format.bytes() as u32;
+        if spec.channels == 0 || spec.sampling_rate == 0 || block_align > u16::MAX as u32
+            || spec.sampling_rate as u64 * block_align as u64 > u32::MAX as u64 {
+            return Err(ErrorKind::BadWavSpec.into());
+        }
+
+        let start = writer.stream_position()?;
+        let fmt_size = if spec.extensible() { FMT_EXTENSIBLE_SIZE } else { FMT_SIZE };
+
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(fmt_size)?;
+        let tag = if spec.extensible() { WAVE_FORMAT_EXTENSIBLE } else { spec.sample_format.tag() };
+        writer.write_u16::<LittleEndian>(tag)?;
+        writer.write_u16::<LittleEndian>(spec.channels)?;
+        writer.write_u32::<LittleEndian>(spec.sampling_rate)?;
+        writer.write_u32::<LittleEndian>(spec.sampling_rate * spec.block_align() as u32)?;
+        writer.write_u16::<LittleEndian>(spec.block_align())?;
+        writer.write_u16::<LittleEndian>(spec.sample_format.bits())?;
+        if spec.extensible() {
+            writer.write_u16::<LittleEndian>((FMT_EXTENSIBLE_SIZE - FMT_SIZE - 2) as u16)?;
+            writer.write_u16::<LittleEndian>(spec.sample_format.bits())?; // valid bits per sample
+            writer.write_u32::<LittleEndian>(channel_mask(spec.channels))?;
+            writer.write_u16::<LittleEndian>(spec.sample_format.tag())?;
+            writer.write_all(&SUBTYPE_GUID)?;
+        }
+
+        let mut fact_length = None;
+        if spec.sample_format == SampleFormat::Float32 {
+            writer.write_all(b"fact")?;
+            writer.write_u32::<LittleEndian>(4)?;
+            fact_length = Some(writer.stream_position()?);
+            writer.write_u32::<LittleEndian>(0)?;
+        }
+
+        writer.write_all(b"data")?;
+        let data_size = writer.stream_position()?;
+        writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(WavWriter {
+            writer,
+            spec,
+            riff_size: start + 4,
+            fact_length,
+            data_size,
+            written: 0,
+            max_size: u32::MAX,
+        })
+    }
+
+    #[inline]
+    pub fn spec(&self) -> &WavSpec {
+        &self.spec
+    }
+
+    // Lowers the RIFF size limit from the format's 4GB, for readers that treat sizes as signed.
+    pub fn set_max_size(&mut self, max_size: u32) {
+        self.max_size = max_size;
+    }
+
+    #[inline]
+    pub fn max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    // Number of sample frames (one sample for every channel) written so far.
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.written / self.spec.block_align() as u64
+    }
+
+    // Writes one sample in -1.0..1.0. Integer formats clip anything outside of that range.
+    // Errors with `RiffTooLarge` without writing anything if the file would pass `max_size`.
+    pub fn write_sample(&mut self, sample: f32) -> Result<(), MpError> {
+        let bytes = self.spec.sample_format.bytes() as u64;
+        if self.data_size_after(bytes) > self.max_size as u64 {
+            return Err(ErrorKind::RiffTooLarge.into());
+        }
+
+        match self.spec.sample_format {
+            SampleFormat::Int16 => self.writer.write_i16::<LittleEndian>(quantize(sample, 16) as i16)?,
+            SampleFormat::Int24 => self.writer.write_i24::<LittleEndian>(quantize(sample, 24))?,
+            SampleFormat::Float32 => self.writer.write_f32::<LittleEndian>(sample)?,
+        }
+        self.written += bytes;
+        Ok(())
+    }
+
+    // Writes interleaved samples, `spec().channels()` per sample frame.
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), MpError> {
+        for &sample in samples {
+            self.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    // Pads the data chunk to an even length, fills in the chunk sizes and hands back the writer,
+    // positioned at the end of the file.
+    pub fn finish(mut self) -> Result<W, MpError> {
+        if self.written % 2 == 1 {
+            self.writer.write_u8(0)?;
+        }
+        let end = self.writer.stream_position()?;
+
+        self.writer.seek(SeekFrom::Start(self.riff_size))?;
+        self.writer.write_u32::<LittleEndian>((end - self.riff_size - 4) as u32)?;
+        if let Some(fact_length) = self.fact_length {
+            self.writer.seek(SeekFrom::Start(fact_length))?;
+            self.writer.write_u32::<LittleEndian>(self.frames() as u32)?;
+        }
+        self.writer.seek(SeekFrom::Start(self.data_size))?;
+        self.writer.write_u32::<LittleEndian>(self.written as u32)?;
+
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    // RIFF size the file would have after `bytes` more sample data, which has to fit in 32 bits.
+    fn data_size_after(&self, bytes: u64) -> u64 {
+        let data = self.written + bytes;
+        self.data_size + 4 + data + data % 2 - self.riff_size - 4
+    }
+}
+
+// Scales and rounds a sample to a signed integer of `bits` bits, clipping at full scale.
+fn quantize(sample: f32, bits: u32) -> i32 {
+    let max = ((1i64 << (bits - 1)) - 1) as f32;
+    let min = -(1i64 << (bits - 1)) as f32;
+    let scaled = sample * (max + 1.0);
+    let rounded = if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 };
+    rounded.max(min).min(max) as i32
+}
+
+// Default speaker layout for a channel count: front left, front right, front center, LFE,
+// back left, back right and so on, in the order of the SPEAKER_* bits.
+fn channel_mask(channels: u16) -> u32 {
+    if channels >= 32 {
+        u32::MAX
+    } else {
+        (1u32 << channels) - 1
+    }
+}
+
+// TODO: A helper decoding a whole stream straight to a WAV file, driving `FrameReader` and
+// feeding every frame's samples to a `WavWriter` with a spec from the first frame's
+// `decoder::Format`. `Frame::format_changed` marks where a stream switches sampling rate or
+// channel layout, where the helper would either error or finish the current file and start a
+// new one. Blocked on the frame reader producing PCM, which needs Huffman decoding,
+// requantization, IMDCT and synthesis first.
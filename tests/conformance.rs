@@ -0,0 +1,141 @@
+// Conformance harness for the ISO/IEC 11172-4 (MPEG-1) and 13818-4 (MPEG-2) compliance
+// bitstreams. The streams are not redistributable, so they are not checked in: put each
+// `<name>.bit` next to its reference output `<name>.pcm` (interleaved 16-bit big endian, as in
+// the ISO distribution) in `mpeg_compliance_testing/` or the directory named by
+// `MPTREE_CONFORMANCE_DIR`; `scripts/fetch_conformance.sh` collects them from a copy of the ISO
+// electronic inserts. The stream comparison is ignored until `FrameReader` decodes to PCM, run it
+// with `cargo test --test conformance -- --ignored`.
+
+extern crate mptree;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Full accuracy: RMS error below 2^-15 / sqrt(12) and no sample off by more than 2^-14.
+// Limited accuracy: RMS error below 2^-11 / sqrt(12). Both relative to a full scale of 1.0.
+#[derive(Debug, PartialEq)]
+enum Accuracy {
+    Full,
+    Limited,
+    NonCompliant,
+}
+
+struct Comparison {
+    rms: f64,
+    max: f64,
+    accuracy: Accuracy,
+}
+
+fn compare(decoded: &[f64], reference: &[f64]) -> Comparison {
+    let length = decoded.len().min(reference.len());
+    let mut sum = 0.0;
+    let mut max: f64 = 0.0;
+    for (a, b) in decoded.iter().zip(reference.iter()) {
+        let difference = (a - b).abs();
+        sum += difference * difference;
+        max = max.max(difference);
+    }
+
+    let rms = if length == 0 { 0.0 } else { (sum / length as f64).sqrt() };
+    let accuracy = if rms < 2f64.powi(-15) / 12f64.sqrt() && max <= 2f64.powi(-14) {
+        Accuracy::Full
+    } else if rms < 2f64.powi(-11) / 12f64.sqrt() {
+        Accuracy::Limited
+    } else {
+        Accuracy::NonCompliant
+    };
+
+    Comparison { rms, max, accuracy }
+}
+
+fn read_reference(path: &Path) -> Vec<f64> {
+    let mut bytes = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+    bytes.chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| i16::from_be_bytes([pair[0], pair[1]]) as f64 / 32768.0)
+        .collect()
+}
+
+// Decodes a whole stream to interleaved samples in [-1.0, 1.0). `FrameReader` does not produce
+// PCM yet, so every stream counts as a failure.
+fn decode(_path: &Path) -> Option<Vec<f64>> {
+    None
+}
+
+fn conformance_dir() -> PathBuf {
+    env::var_os("MPTREE_CONFORMANCE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("mpeg_compliance_testing"))
+}
+
+fn streams(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            streams(&path, found);
+        } else if path.extension().is_some_and(|extension| extension == "bit") {
+            found.push(path);
+        }
+    }
+}
+
+#[test]
+fn accuracy_classification() {
+    let reference = vec![0.0, 0.5, -0.5, 0.25];
+
+    let exact = compare(&reference, &reference);
+    assert_eq!(exact.accuracy, Accuracy::Full);
+    assert_eq!(exact.rms, 0.0);
+
+    let fine: Vec<f64> = reference.iter().map(|sample| sample + 2f64.powi(-18)).collect();
+    assert_eq!(compare(&fine, &reference).accuracy, Accuracy::Full);
+
+    let coarse: Vec<f64> = reference.iter().map(|sample| sample + 2f64.powi(-13)).collect();
+    assert_eq!(compare(&coarse, &reference).accuracy, Accuracy::Limited);
+
+    let wrong: Vec<f64> = reference.iter().map(|sample| -sample).collect();
+    assert_eq!(compare(&wrong, &reference).accuracy, Accuracy::NonCompliant);
+}
+
+#[test]
+#[ignore = "FrameReader does not decode to PCM yet"]
+fn compliance_streams() {
+    let dir = conformance_dir();
+    assert!(dir.is_dir(), "{} not found, see scripts/fetch_conformance.sh", dir.display());
+
+    let mut found = Vec::new();
+    streams(&dir, &mut found);
+    found.sort();
+    assert!(!found.is_empty(), "no compliance streams in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for stream in found {
+        let reference = stream.with_extension("pcm");
+        if !reference.is_file() {
+            println!("{}: no reference output", stream.display());
+            continue;
+        }
+
+        match decode(&stream) {
+            Some(decoded) => {
+                let comparison = compare(&decoded, &read_reference(&reference));
+                println!(
+                    "{}: rms {:e}, max {:e}, {:?}",
+                    stream.display(), comparison.rms, comparison.max, comparison.accuracy,
+                );
+                if comparison.accuracy == Accuracy::NonCompliant {
+                    failures.push(stream);
+                }
+            },
+            None => {
+                println!("{}: not decoded", stream.display());
+                failures.push(stream);
+            },
+        }
+    }
+
+    assert!(failures.is_empty(), "non-compliant or undecoded streams: {:?}", failures);
+}
@@ -0,0 +1,363 @@
+// Cutting and splitting the example files: frame counts, the regenerated Xing header and the
+// gapless information for each way of fixing the reservoir.
+
+extern crate mptree;
+
+use std::fs;
+use std::io::Cursor;
+
+use mptree::edit::{self, Editor, EditSummary, ReservoirFix};
+use mptree::error::{ErrorKind, Mismatch};
+use mptree::header::{ChannelMode, Header, Layer};
+use mptree::probe::{self, Scan};
+use mptree::side_info::{self, SideInformation};
+use mptree::tags::Tags;
+use mptree::vbr::{self, BitrateMode, LameHeader, VbrHeader, XingHeader, LAME_SIZE, XING_QUALITY};
+
+const SAMPLES_PER_FRAME: u64 = 1152;
+
+fn editor(path: &str) -> Editor<Cursor<Vec<u8>>> {
+    Editor::new(Cursor::new(fs::read(path).unwrap())).unwrap()
+}
+
+// Every frame between the tags, the Xing frame included, by walking the frame sizes.
+fn frames(data: &[u8]) -> Vec<&[u8]> {
+    let audio = Tags::locate(&mut Cursor::new(data)).unwrap().audio();
+    let mut frames = Vec::new();
+    let mut position = audio.start as usize;
+    while position < audio.end as usize {
+        let header = Header::new(&data[position..position + 4]).unwrap();
+        let end = position + header.frame_size() as usize;
+        frames.push(&data[position..end]);
+        position = end;
+    }
+    assert_eq!(position, audio.end as usize);
+    frames
+}
+
+// The output's Xing frame count and byte count, and the LAME delay and padding, checked against
+// the summary and the frames actually written.
+fn check_output(output: &[u8], summary: &EditSummary) {
+    assert_eq!(output.len() as u64, summary.bytes());
+    assert_eq!(frames(output).len() as u64, summary.frames() + 1);
+
+    let info = probe::probe(&mut Cursor::new(output), Scan::Full).unwrap();
+    assert_eq!(info.first_frame(), 0);
+    assert_eq!(info.frames(), summary.frames());
+    let xing = match info.vbr_header() {
+        Some(VbrHeader::Xing(xing)) => xing,
+        header => panic!("no Xing header: {:?}", header),
+    };
+    assert_eq!(xing.frames(), Some(summary.frames() as u32));
+    assert_eq!(xing.bytes(), Some(summary.bytes() as u32));
+
+    let lame = xing.lame().unwrap();
+    assert_eq!(lame.encoder_delay(), summary.encoder_delay());
+    assert_eq!(lame.padding(), summary.padding());
+}
+
+// The output's LAME header.
+fn lame(output: &[u8]) -> LameHeader {
+    let header = Header::new(&output[..4]).unwrap();
+    *XingHeader::parse(&header, output).unwrap().lame().unwrap()
+}
+
+fn extract(editor: &mut Editor<Cursor<Vec<u8>>>, start: u64, end: u64) -> (Vec<u8>, EditSummary) {
+    let mut output = Cursor::new(Vec::new());
+    let summary = editor.extract(start..end, &mut output).unwrap();
+    let output = output.into_inner();
+    check_output(&output, &summary);
+    (output, summary)
+}
+
+// Encoder delay and padding of the input's LAME header.
+fn gapless(path: &str) -> (u16, u16) {
+    let data = fs::read(path).unwrap();
+    match probe::probe(&mut Cursor::new(&data[..]), Scan::Bitrate).unwrap().vbr_header() {
+        Some(VbrHeader::Xing(xing)) => xing.lame().map_or((0, 0), |lame| (lame.encoder_delay(), lame.padding())),
+        _ => (0, 0),
+    }
+}
+
+// The same stream with the padding in its LAME header changed.
+fn with_padding(mut data: Vec<u8>, padding: u16) -> Vec<u8> {
+    let header = Header::new(&data[..4]).unwrap();
+    let mut xing = XingHeader::parse(&header, &data).unwrap();
+    let mut lame = *xing.lame().unwrap();
+    lame.set_padding(padding);
+    xing.set_lame(Some(lame));
+    xing.write(&header, &mut data).unwrap();
+    data
+}
+
+#[test]
+fn carry_over_adds_carriers_to_the_delay() {
+    let input = fs::read("examples/whatislove.mp3").unwrap();
+    let input_frames = frames(&input);
+    let mut editor = editor("examples/whatislove.mp3");
+    assert_eq!(editor.reservoir_fix(), ReservoirFix::CarryOver);
+
+    let (output, summary) = extract(&mut editor, 100, 200);
+    assert!(summary.carrier_frames() > 0);
+    assert_eq!(summary.silenced_frames(), 0);
+    assert_eq!(summary.frames(), 100 + summary.carrier_frames());
+    assert_eq!(summary.encoder_delay() as u64, summary.carrier_frames() * SAMPLES_PER_FRAME);
+    assert_eq!(summary.padding(), 0);
+
+    // Past the Xing frames and the carriers, the input frames are copied unchanged.
+    let output_frames = frames(&output);
+    let copied = &output_frames[1 + summary.carrier_frames() as usize..];
+    assert_eq!(copied, &input_frames[1 + 100..1 + 200]);
+}
+
+#[test]
+fn silence_replaces_frames_that_reach_back() {
+    let mut editor = editor("examples/whatislove.mp3");
+    editor.set_reservoir_fix(ReservoirFix::Silence);
+
+    let (_, summary) = extract(&mut editor, 100, 200);
+    assert_eq!(summary.carrier_frames(), 0);
+    assert!(summary.silenced_frames() > 0);
+    assert_eq!(summary.frames(), 100);
+    assert_eq!(summary.encoder_delay(), 0);
+    assert_eq!(summary.padding(), 0);
+}
+
+#[test]
+fn cut_at_the_first_and_last_frame() {
+    let (delay, padding) = gapless("examples/whatislove.mp3");
+    assert_eq!((delay, padding), (576, 0));
+    let mut editor = editor("examples/whatislove.mp3");
+    let frames = editor.frames();
+
+    // Nothing to carry over at the start, the input's delay is kept.
+    let (_, summary) = extract(&mut editor, 0, 10);
+    assert_eq!(summary.carrier_frames(), 0);
+    assert_eq!(summary.silenced_frames(), 0);
+    assert_eq!(summary.frames(), 10);
+    assert_eq!(summary.encoder_delay(), delay);
+    assert_eq!(summary.padding(), 0);
+
+    let mut output = Cursor::new(Vec::new());
+    assert!(editor.extract(frames - 1..frames + 1, &mut output).is_err());
+    assert!(editor.extract(5..5, &mut output).is_err());
+
+    // The whole stream, given some padding to keep.
+    let (whole, summary) = extract(&mut editor, 0, frames);
+    assert_eq!(summary.frames(), frames);
+    assert_eq!((summary.encoder_delay(), summary.padding()), (delay, 0));
+    let mut editor = Editor::new(Cursor::new(with_padding(whole, 700))).unwrap();
+    assert_eq!(editor.frames(), frames);
+
+    // The last frame keeps the padding, after the carriers in front of it.
+    let (_, summary) = extract(&mut editor, frames - 1, frames);
+    assert_eq!(summary.frames(), 1 + summary.carrier_frames());
+    assert_eq!(summary.encoder_delay() as u64, summary.carrier_frames() * SAMPLES_PER_FRAME);
+    assert_eq!(summary.padding(), 700);
+
+    // One frame earlier, none of it is padding.
+    let (_, summary) = extract(&mut editor, frames - 2, frames - 1);
+    assert_eq!(summary.padding(), 0);
+}
+
+#[test]
+fn split_covers_every_frame() {
+    for path in ["examples/whatislove.mp3", "examples/3-note.mp3"].iter() {
+        let mut editor = editor(path);
+        let frames = editor.frames();
+
+        let mut outputs = vec![Vec::new(); 3];
+        let summaries = {
+            let mut buffers = outputs.iter_mut();
+            editor.split(&[frames / 3, frames / 2], |_| Ok(Cursor::new(buffers.next().unwrap()))).unwrap()
+        };
+        assert_eq!(summaries.len(), 3);
+        assert_eq!(summaries[0].carrier_frames(), 0);
+        let total: u64 = summaries.iter().map(|summary| summary.frames() - summary.carrier_frames()).sum();
+        assert_eq!(total, frames);
+
+        for (output, summary) in outputs.iter().zip(&summaries) {
+            check_output(output, summary);
+        }
+    }
+}
+
+// whatislove.mp3 with a peak of 1.0 and a radio gain of -1.6 dB in its LAME header.
+fn with_replay_gain() -> Vec<u8> {
+    let mut data = fs::read("examples/whatislove.mp3").unwrap();
+    let start = Tags::locate(&mut Cursor::new(&data[..])).unwrap().audio().start as usize;
+    let header = Header::new(&data[start..start + 4]).unwrap();
+    let lame = start + vbr::lame_offset(&header, &data[start..]).unwrap();
+    data[lame + 11..lame + 19].copy_from_slice(&[0x00, 0x80, 0x00, 0x00, 0x2C, 0x10, 0x00, 0x00]);
+    data
+}
+
+// Silent 128 kbps frames in the format of whatislove.mp3, which is 256 kbps throughout.
+fn silent_stream(count: usize) -> Vec<u8> {
+    let header = Header::new(&[0xFF, 0xFB, 0x90, 0x00]).unwrap();
+    let mut frame = vec![0u8; header.frame_size() as usize];
+    side_info::write_frame_head(&header, &SideInformation::silent(&header), &mut frame).unwrap();
+    frame.iter().cycle().take(frame.len() * count).cloned().collect()
+}
+
+#[test]
+fn concat_joins_whole_streams() {
+    let input = fs::read("examples/whatislove.mp3").unwrap();
+    let input_frames = frames(&input);
+    let mut inputs = vec![editor("examples/whatislove.mp3"), editor("examples/whatislove.mp3")];
+    let frames_per_input = inputs[0].frames();
+
+    let mut output = Cursor::new(Vec::new());
+    let summary = edit::concat(&mut inputs, &mut output).unwrap();
+    let output = output.into_inner();
+    check_output(&output, &summary);
+    assert_eq!(summary.frames(), 2 * frames_per_input);
+    assert_eq!((summary.carrier_frames(), summary.silenced_frames()), (0, 0));
+    assert_eq!((summary.encoder_delay(), summary.padding()), (576, 0));
+
+    let output_frames = frames(&output);
+    let audio = &input_frames[1..];
+    assert_eq!(&output_frames[1..audio.len() + 1], audio);
+    assert_eq!(&output_frames[audio.len() + 1..], audio);
+
+    let lame = lame(&output);
+    assert_eq!(lame.bitrate_mode(), Some(BitrateMode::Constant));
+    assert_eq!(lame.music_length() as u64, summary.bytes());
+}
+
+#[test]
+fn concat_corrects_the_lame_header() {
+    let silence = silent_stream(20);
+    let mut inputs = vec![
+        Editor::new(Cursor::new(with_replay_gain())).unwrap(),
+        Editor::new(Cursor::new(silence)).unwrap(),
+    ];
+    assert_eq!(inputs[1].frames(), 20);
+
+    let mut output = Cursor::new(Vec::new());
+    let summary = edit::concat(&mut inputs, &mut output).unwrap();
+    let output = output.into_inner();
+    check_output(&output, &summary);
+
+    // The first input's CBR method and measurements don't describe the joined stream.
+    let lame = lame(&output);
+    assert_eq!(lame.bitrate_mode(), Some(BitrateMode::Variable));
+    assert_eq!(lame.replay_gain(), &[0; 8]);
+    assert_eq!(lame.peak(), None);
+    assert_eq!(&lame.encoder()[..4], b"Lavf");
+}
+
+#[test]
+fn extract_keeps_replay_gain_only_for_the_whole_stream() {
+    let mut editor = Editor::new(Cursor::new(with_replay_gain())).unwrap();
+    let frames = editor.frames();
+
+    let (whole, _) = extract(&mut editor, 0, frames);
+    assert_eq!(lame(&whole).peak(), Some(1.0));
+    assert_eq!(&lame(&whole).replay_gain()[4..6], &[0x2C, 0x10]);
+
+    let (part, _) = extract(&mut editor, 0, frames - 1);
+    assert_eq!(lame(&part).peak(), None);
+    assert_eq!(lame(&part).replay_gain(), &[0; 8]);
+}
+
+#[test]
+fn concat_refuses_other_formats() {
+    let mut inputs = vec![editor("examples/whatislove.mp3"), editor("examples/3-note.mp3")];
+    let mut output = Cursor::new(Vec::new());
+    let err = edit::concat(&mut inputs, &mut output).unwrap_err();
+    match *err.kind() {
+        ErrorKind::Incompatible { input: 1, frame: 0, mismatch: Mismatch::ChannelMode(_, ChannelMode::Mono) } => (),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+    assert!(output.into_inner().is_empty());
+    assert_eq!(err.to_string(), "input 1 can't be joined, frame 0 is Mono instead of JointStereo(Stereo(true, false))");
+    assert_eq!(Mismatch::Layer(Layer::Layer3, Layer::Layer2).to_string(), "Layer2 instead of Layer3");
+}
+
+#[test]
+fn lame_header_follows_every_xing_field() {
+    let first = Header::new(&[0xFF, 0xFB, 0x90, 0x00]).unwrap();
+    let toc = vbr::toc(&[0, 1000, 2000, 3000], 4000);
+    let mut xing = XingHeader::new(false, 4, 4000, toc);
+    let mut lame = LameHeader::new(BitrateMode::Variable, 576, 1000);
+    lame.set_music(4000, 0x1234);
+    xing.set_lame(Some(lame));
+
+    let header = xing.frame_header(&first).unwrap();
+    let mut frame = vec![0u8; header.frame_size() as usize];
+    xing.write(&header, &mut frame).unwrap();
+
+    // Frames, bytes, TOC and quality, as LAME writes them.
+    let tag = vbr::xing_offset(&header);
+    let flags = u32::from_be_bytes([frame[tag + 4], frame[tag + 5], frame[tag + 6], frame[tag + 7]]);
+    assert_eq!(flags, 0xF);
+    assert_ne!(flags & XING_QUALITY, 0);
+    let position = tag + 8 + 4 + 4 + vbr::XING_TOC_SIZE + 4;
+    assert_eq!(xing.size(&header), position + LAME_SIZE);
+
+    let written = LameHeader::parse(&frame[position..position + LAME_SIZE]).unwrap();
+    assert_eq!(&written.encoder()[..4], b"LAME");
+    assert_eq!(written.bitrate_mode(), Some(BitrateMode::Variable));
+    assert_eq!((written.encoder_delay(), written.padding()), (576, 1000));
+    assert_eq!((written.music_length(), written.music_crc()), (4000, 0x1234));
+
+    let parsed = XingHeader::parse(&header, &frame).unwrap();
+    assert_eq!(parsed.quality(), Some(0));
+    assert_eq!(parsed.frames(), Some(4));
+    assert_eq!(parsed.toc(), Some(&toc));
+    assert_eq!(parsed.lame(), Some(&written));
+}
+
+#[test]
+fn repair_naive_concatenation() {
+    // The second copy's ID3v2 tag is junk between frames and gets dropped, along with its
+    // Xing frame.
+    let first = fs::read("examples/whatislove.mp3").unwrap();
+    let mut data = first.clone();
+    data.extend_from_slice(&first);
+
+    let mut file = Cursor::new(data);
+    let summary = edit::repair_vbr_header(&mut file, true).unwrap();
+    let repaired = file.into_inner();
+    assert_eq!(&repaired[..138], &first[..138]);
+
+    let audio = frames(&repaired);
+    let expected: Vec<&[u8]> = frames(&first)[1..].iter().chain(frames(&first)[1..].iter()).cloned().collect();
+    assert_eq!(summary.frames(), 2 * 1048);
+    assert_eq!(audio.len() as u64, summary.frames() + 1);
+    assert!(audio[1..] == expected[..]);
+
+    let audio_bytes: usize = audio.iter().map(|frame| frame.len()).sum();
+    assert_eq!(audio_bytes as u64, summary.bytes());
+    let header = Header::new(&audio[0][..4]).unwrap();
+    let xing = XingHeader::parse(&header, audio[0]).unwrap();
+    assert_eq!(xing.frames(), Some(2 * 1048));
+    assert_eq!(xing.bytes(), Some(audio_bytes as u32));
+    // whatislove.mp3 is constant bitrate.
+    assert!(xing.info());
+
+    let toc = xing.toc().unwrap();
+    assert_eq!(toc[0], 0);
+    assert!(toc.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert!((toc[50] as i32 - 128).abs() <= 2);
+
+    // Kept from the first copy.
+    assert_eq!(xing.lame().unwrap().encoder_delay(), 576);
+}
+
+#[test]
+fn repair_refuses_mixed_formats() {
+    // whatislove.mp3 is joint stereo, 3-note.mp3 mono.
+    let mut data = fs::read("examples/whatislove.mp3").unwrap();
+    let first_frames = frames(&data).len() as u64 - 1;
+    data.extend_from_slice(&fs::read("examples/3-note.mp3").unwrap());
+
+    let mut file = Cursor::new(data.clone());
+    let err = edit::repair_vbr_header(&mut file, true).unwrap_err();
+    match *err.kind() {
+        ErrorKind::Incompatible { input: 0, frame, mismatch: Mismatch::ChannelMode(_, ChannelMode::Mono) } => assert_eq!(frame, first_frames),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+    assert!(file.into_inner() == data);
+}
@@ -0,0 +1,188 @@
+// Gain changes on the example files: undoing them, the mp3gain APEv2 records, clamping and the
+// LAME music CRC.
+
+extern crate crc16;
+extern crate mptree;
+
+use std::fs;
+use std::io::Cursor;
+
+use crc16::{State, ARC};
+use mptree::ape::ApeTag;
+use mptree::edit;
+use mptree::gain;
+use mptree::header::Header;
+use mptree::probe::{self, Scan};
+use mptree::tags::Tags;
+use mptree::vbr::{self, LameHeader, VbrHeader, LAME_SIZE};
+
+const EXAMPLES: [&str; 2] = ["examples/whatislove.mp3", "examples/3-note.mp3"];
+
+fn ape_tag(data: &[u8]) -> Option<ApeTag> {
+    let tags = Tags::locate(&mut Cursor::new(data)).unwrap();
+    tags.ape().map(|range| ApeTag::parse(&data[range.start as usize..range.end as usize]).unwrap())
+}
+
+// Offset and size of the Xing frame, the offset of the LAME header in it, and the LAME header.
+fn lame(data: &[u8]) -> (usize, usize, usize, LameHeader) {
+    let info = probe::probe(&mut Cursor::new(data), Scan::Bitrate).unwrap();
+    let start = info.first_frame() as usize;
+    let header = Header::new(&data[start..start + 4]).unwrap();
+    let offset = vbr::lame_offset(&header, &data[start..]).unwrap();
+    match info.vbr_header() {
+        Some(VbrHeader::Xing(xing)) => (start, header.frame_size() as usize, offset, *xing.lame().unwrap()),
+        _ => panic!("no Xing header"),
+    }
+}
+
+#[test]
+fn apply_then_undo_is_byte_identical() {
+    for path in EXAMPLES.iter() {
+        let original = fs::read(path).unwrap();
+        let mut file = Cursor::new(original.clone());
+
+        let before = gain::analyze(&mut file).unwrap();
+        let info = gain::apply_gain(&mut file, 4, true).unwrap();
+        assert_eq!(info.clamped(), 0);
+        assert_eq!(info.undo(), 4);
+        assert_eq!(info.min_gain(), before.min_gain().map(|gain| gain + 4));
+        assert_eq!(info.max_gain(), before.max_gain().map(|gain| gain + 4));
+        assert_ne!(file.get_ref(), &original);
+
+        let info = gain::undo_gain(&mut file).unwrap();
+        assert_eq!(info.undo(), 0);
+        assert_eq!(info.min_gain(), before.min_gain());
+        assert!(file.get_ref() == &original, "{} changed after undo", path);
+    }
+}
+
+#[test]
+fn undo_records() {
+    // 3-note.mp3 ends with an ID3v1 tag, which has to stay last.
+    let original = fs::read("examples/3-note.mp3").unwrap();
+    let mut file = Cursor::new(original.clone());
+    gain::apply_gain(&mut file, 3, true).unwrap();
+    let info = gain::apply_gain(&mut file, -1, true).unwrap();
+    assert_eq!(info.undo(), 2);
+
+    let data = file.get_ref();
+    assert_eq!(&data[data.len() - 128..], &original[original.len() - 128..]);
+    let ape = ape_tag(data).unwrap();
+    // The record holds the change that reverts the gain.
+    assert_eq!(ape.text("MP3GAIN_UNDO"), Some("-002,-002,N"));
+    let minmax = format!("{:03},{:03}", info.min_gain().unwrap(), info.max_gain().unwrap());
+    assert_eq!(ape.text("mp3gain_minmax"), Some(minmax.as_str()));
+
+    // A later run picks the record up.
+    assert_eq!(gain::analyze(&mut file).unwrap().undo(), 2);
+
+    gain::undo_gain(&mut file).unwrap();
+    assert!(ape_tag(file.get_ref()).is_none());
+    assert!(file.get_ref() == &original);
+}
+
+// An APEv2 tag with a header and footer and the two items mp3gain writes, laid out byte by byte
+// rather than with `ApeTag`.
+fn mp3gain_tag(minmax: &str, undo: &str) -> Vec<u8> {
+    let mut items = Vec::new();
+    for &(key, value) in [("MP3GAIN_MINMAX", minmax), ("MP3GAIN_UNDO", undo)].iter() {
+        items.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        items.extend_from_slice(&[0; 4]); // UTF-8 text item
+        items.extend_from_slice(key.as_bytes());
+        items.push(0);
+        items.extend_from_slice(value.as_bytes());
+    }
+
+    let mut tag = Vec::new();
+    for &flags in [0xA000_0000u32, 0x8000_0000].iter() {
+        if flags == 0x8000_0000 {
+            tag.extend_from_slice(&items);
+        }
+        tag.extend_from_slice(b"APETAGEX");
+        tag.extend_from_slice(&2000u32.to_le_bytes());
+        tag.extend_from_slice(&(items.len() as u32 + 32).to_le_bytes());
+        tag.extend_from_slice(&2u32.to_le_bytes());
+        tag.extend_from_slice(&flags.to_le_bytes());
+        tag.extend_from_slice(&[0; 8]);
+    }
+    tag
+}
+
+#[test]
+fn undo_a_record_written_by_mp3gain() {
+    // mp3gain lowering the file by 3 steps stores "+003,+003,N", the change that undoes it.
+    let original = fs::read("examples/3-note.mp3").unwrap();
+    let mut file = Cursor::new(original.clone());
+    gain::apply_gain(&mut file, -3, true).unwrap();
+
+    let lowered = file.get_ref();
+    let tags = Tags::locate(&mut Cursor::new(lowered)).unwrap();
+    let mut data = lowered[..tags.audio().end as usize].to_vec();
+    data.extend_from_slice(&mp3gain_tag("097,205", "+003,+003,N"));
+    data.extend_from_slice(&original[original.len() - 128..]);
+    let mut file = Cursor::new(data);
+
+    assert_eq!(gain::analyze(&mut file).unwrap().undo(), -3);
+    gain::undo_gain(&mut file).unwrap();
+    assert!(file.get_ref() == &original);
+}
+
+#[test]
+fn gains_are_clamped_per_granule() {
+    // whatislove.mp3 has audible granules with a global_gain of 0.
+    let mut file = Cursor::new(fs::read("examples/whatislove.mp3").unwrap());
+    let before = gain::analyze(&mut file).unwrap();
+    assert_eq!(before.min_gain(), Some(0));
+    assert_eq!(before.min_steps(), 0);
+
+    let info = gain::apply_gain(&mut file, -2, false).unwrap();
+    assert!(info.clamped() > 0);
+    assert_eq!(info.min_gain(), Some(0));
+    assert_eq!(info.max_gain(), before.max_gain().map(|gain| gain - 2));
+    assert_eq!(info.undo(), -2);
+}
+
+#[test]
+fn music_crc_follows_the_audio() {
+    // Rewriting the VBR header fills in the music length and CRC that whatislove.mp3 leaves zeroed.
+    let mut file = Cursor::new(fs::read("examples/whatislove.mp3").unwrap());
+    edit::repair_vbr_header(&mut file, true).unwrap();
+    let repaired = file.get_ref().clone();
+    let (_, _, _, before) = lame(&repaired);
+    assert!(before.music_length() > 0);
+
+    gain::apply_gain(&mut file, 2, true).unwrap();
+    let data = file.get_ref();
+    let (start, frame_size, offset, after) = lame(data);
+    assert_eq!(after.music_length(), before.music_length());
+    assert_ne!(after.music_crc(), before.music_crc());
+
+    // The music length counts from the start of the Xing frame.
+    let audio = &data[start + frame_size..start + after.music_length() as usize];
+    assert_eq!(after.music_crc(), State::<ARC>::calculate(audio));
+    assert_eq!(after.crc(), State::<ARC>::calculate(&data[start..start + offset + LAME_SIZE - 2]));
+
+    gain::undo_gain(&mut file).unwrap();
+    assert!(file.get_ref() == &repaired);
+}
+
+#[test]
+fn false_sync_in_junk_is_left_alone() {
+    // Junk in front of the first frame with a frame header in it, a 104 byte 32 kbps frame that
+    // isn't followed by another header.
+    let original = fs::read("examples/3-note.mp3").unwrap();
+    let tags = Tags::locate(&mut Cursor::new(&original[..])).unwrap();
+    let start = tags.audio().start as usize;
+    let mut junk = vec![0xFF, 0xFB, 0x10, 0xC0, 0x00];
+    junk.resize(150, 0x5A);
+
+    let mut data = original[..start].to_vec();
+    data.extend_from_slice(&junk);
+    data.extend_from_slice(&original[start..]);
+    let mut file = Cursor::new(data.clone());
+
+    gain::apply_gain(&mut file, 2, true).unwrap();
+    assert_eq!(&file.get_ref()[start..start + junk.len()], &junk[..]);
+    gain::undo_gain(&mut file).unwrap();
+    assert!(file.get_ref() == &data);
+}
@@ -0,0 +1,263 @@
+// ID3v2 tags: round trips through both versions, frames that can't be decoded, and writing tags
+// into the example files.
+
+extern crate mptree;
+
+use std::fs;
+use std::io::Cursor;
+
+use mptree::error::ErrorKind;
+use mptree::id3::{self, Id3Frame, Id3Tag, Id3Version, DEFAULT_PADDING, MAX_TAG_SIZE, NO_OFFSET};
+use mptree::tags::Tags;
+
+const EXAMPLES: [&str; 2] = ["examples/whatislove.mp3", "examples/3-note.mp3"];
+
+fn text(id: &[u8; 4], value: &str) -> Id3Frame {
+    Id3Frame::Text { id: *id, values: vec![String::from(value)] }
+}
+
+// One frame of every kind, text that doesn't fit in ISO-8859-1 included.
+fn every_frame(version: Id3Version) -> Id3Tag {
+    let mut tag = Id3Tag::new(version);
+    tag.add(text(b"TIT2", "What Is Love"));
+    tag.add(text(b"TPE1", "Haddaway \u{2013} \u{30cf}"));
+    tag.add(Id3Frame::UserText { description: String::from("replaygain_track_gain"), value: String::from("-6.5 dB") });
+    tag.add(Id3Frame::Comment { language: *b"eng", description: String::new(), text: String::from("caf\u{e9}") });
+    tag.add(Id3Frame::Picture {
+        mime_type: String::from("image/png"),
+        picture_type: 3,
+        description: String::from("front"),
+        data: (0..=255).collect(),
+    });
+    tag.add(Id3Frame::Private { owner: String::from("mptree"), data: vec![0, 1, 2, 0xFF] });
+    tag.add(Id3Frame::TableOfContents {
+        element_id: String::from("toc"),
+        top_level: true,
+        ordered: true,
+        children: vec![String::from("ch0"), String::from("ch1")],
+        frames: vec![text(b"TIT2", "Chapters")],
+    });
+    for (index, &(start, end)) in [(0, 1500), (1500, 4000)].iter().enumerate() {
+        tag.add(Id3Frame::Chapter {
+            element_id: format!("ch{}", index),
+            start_time: start,
+            end_time: end,
+            start_offset: NO_OFFSET,
+            end_offset: NO_OFFSET,
+            frames: vec![text(b"TIT2", &format!("Part {}", index + 1))],
+        });
+    }
+    tag.add(Id3Frame::Raw { id: *b"XYZW", data: vec![1, 2, 3] });
+    tag
+}
+
+// An ID3v2 tag of `version` around the given frame bytes, without padding.
+fn raw_tag(version: Id3Version, frames: &[u8]) -> Vec<u8> {
+    let size = frames.len() as u32;
+    let mut data = vec![b'I', b'D', b'3', if version == Id3Version::V3 { 3 } else { 4 }, 0, 0];
+    data.extend_from_slice(&[(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F]);
+    data.extend_from_slice(frames);
+    data
+}
+
+// The bytes after the ID3v2 tag.
+fn after_tag(data: &[u8]) -> &[u8] {
+    let tags = Tags::locate(&mut Cursor::new(data)).unwrap();
+    &data[tags.audio().start as usize..]
+}
+
+#[test]
+fn round_trips() {
+    for &version in [Id3Version::V3, Id3Version::V4].iter() {
+        let tag = every_frame(version);
+        let data = tag.to_bytes(0);
+        assert_eq!(data.len(), tag.size());
+        assert_eq!(data[3], if version == Id3Version::V3 { 3 } else { 4 });
+        assert_eq!(Id3Tag::parse(&data), Some(tag.clone()));
+
+        let padded = tag.to_bytes(100);
+        assert_eq!(padded.len(), tag.size() + 100);
+        assert!(padded[tag.size()..].iter().all(|&byte| byte == 0));
+        assert_eq!(Id3Tag::parse(&padded), Some(tag.clone()));
+    }
+
+    // Converting only changes how the frames are stored.
+    let mut tag = every_frame(Id3Version::V3);
+    tag.set_version(Id3Version::V4).unwrap();
+    assert_eq!(Id3Tag::parse(&tag.to_bytes(0)), Some(every_frame(Id3Version::V4)));
+}
+
+#[test]
+fn set_text_checks_the_id() {
+    let mut tag = Id3Tag::new(Id3Version::V4);
+    tag.set_text("TALB", "First").unwrap();
+    tag.set_text("TALB", "Second").unwrap();
+    assert_eq!(tag.frames().len(), 1);
+    assert_eq!(tag.text("TALB"), Some("Second"));
+
+    for &id in ["", "TIT", "TIT23", "tit2", "T\u{c9}T", "COMM", "TXXX"].iter() {
+        let err = tag.set_text(id, "value").unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::BadFrameId), "{:?}", id);
+    }
+    assert_eq!(tag.frames().len(), 1);
+}
+
+#[test]
+fn encoded_frames_are_kept() {
+    // A compressed and an encrypted v2.3 frame, with the tag alter preservation status flag.
+    let mut frames = Vec::new();
+    frames.extend_from_slice(b"TIT2\x00\x00\x00\x07\x80\x80\x00\x00\x00\x20x\x9c\x03");
+    frames.extend_from_slice(b"APIC\x00\x00\x00\x03\x00\x40\x81\xAA\xBB");
+    frames.extend_from_slice(b"TALB\x00\x00\x00\x04\x00\x00\x00abc");
+    let data = raw_tag(Id3Version::V3, &frames);
+
+    let mut tag = Id3Tag::parse(&data).unwrap();
+    assert_eq!(tag.frames()[0], Id3Frame::Encoded { id: *b"TIT2", flags: [0x80, 0x80], data: frames[10..17].to_vec() });
+    assert_eq!(tag.frames()[1], Id3Frame::Encoded { id: *b"APIC", flags: [0x00, 0x40], data: vec![0x81, 0xAA, 0xBB] });
+    assert_eq!(tag.text("TALB"), Some("abc"));
+    assert_eq!(tag.to_bytes(0), data);
+
+    // Their flags mean something else in the other version.
+    let err = tag.set_version(Id3Version::V4).unwrap_err();
+    assert!(matches!(*err.kind(), ErrorKind::Id3Conversion));
+    assert_eq!(tag.version(), Id3Version::V3);
+    tag.set_version(Id3Version::V3).unwrap();
+
+    // Also inside a chapter, with the v2.4 flags.
+    let mut chapter = b"CHAP\x00\x00\x00\x20\x00\x00ch0\x00\x00\x00\x00\x00\x00\x00\x03\xE8\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF".to_vec();
+    chapter.extend_from_slice(b"TIT2\x00\x00\x00\x02\x00\x04\x01\x02");
+    let data = raw_tag(Id3Version::V4, &chapter);
+    let mut tag = Id3Tag::parse(&data).unwrap();
+    match tag.frames()[0] {
+        Id3Frame::Chapter { ref frames, .. } => assert_eq!(frames[0], Id3Frame::Encoded { id: *b"TIT2", flags: [0x00, 0x04], data: vec![1, 2] }),
+        ref frame => panic!("{:?}", frame),
+    }
+    assert_eq!(tag.to_bytes(0), data);
+    assert!(tag.set_version(Id3Version::V3).is_err());
+}
+
+#[test]
+fn grouped_frames() {
+    // The group id comes first, then the ID3v2.4 data length indicator.
+    let data = raw_tag(Id3Version::V3, b"TIT2\x00\x00\x00\x05\x00\x20\x07\x00abc");
+    assert_eq!(Id3Tag::parse(&data).unwrap().text("TIT2"), Some("abc"));
+
+    let data = raw_tag(Id3Version::V4, b"TIT2\x00\x00\x00\x09\x00\x41\x07\x00\x00\x00\x04\x03abc");
+    assert_eq!(Id3Tag::parse(&data).unwrap().text("TIT2"), Some("abc"));
+}
+
+#[test]
+fn malformed_frames_are_kept() {
+    // A COMM frame too short for its language, a grouped TIT2 with nothing after the group id
+    // and a valid TALB.
+    let mut frames = Vec::new();
+    frames.extend_from_slice(b"COMM\x00\x00\x00\x03\x00\x00\x03en");
+    frames.extend_from_slice(b"TIT2\x00\x00\x00\x01\x00\x40\x07");
+    frames.extend_from_slice(b"TALB\x00\x00\x00\x04\x00\x00\x03abc");
+    let data = raw_tag(Id3Version::V4, &frames);
+
+    let tag = Id3Tag::parse(&data).unwrap();
+    assert_eq!(tag.frames()[0], Id3Frame::Raw { id: *b"COMM", data: b"\x03en".to_vec() });
+    assert_eq!(tag.frames()[1], Id3Frame::Encoded { id: *b"TIT2", flags: [0x00, 0x40], data: vec![0x07] });
+    assert_eq!(tag.text("TALB"), Some("abc"));
+    assert_eq!(tag.to_bytes(0), data);
+}
+
+#[test]
+fn write_in_place() {
+    for path in EXAMPLES.iter() {
+        let original = fs::read(path).unwrap();
+        let space = original.len() - after_tag(&original).len();
+        let mut file = Cursor::new(original.clone());
+
+        let mut tag = Id3Tag::new(Id3Version::V3);
+        tag.set_text("TIT2", "Short").unwrap();
+        assert!(id3::write_tag(&mut file, &tag).unwrap());
+
+        let data = file.get_ref();
+        assert_eq!(data.len(), original.len());
+        assert_eq!(after_tag(data), after_tag(&original));
+        assert_eq!(Id3Tag::parse(&data[..space]), Some(tag));
+    }
+}
+
+#[test]
+fn write_with_room() {
+    for path in EXAMPLES.iter() {
+        let original = fs::read(path).unwrap();
+        let mut file = Cursor::new(original.clone());
+
+        // Larger than either example's tag and padding.
+        let mut tag = every_frame(Id3Version::V4);
+        tag.add(Id3Frame::Private { owner: String::from("filler"), data: vec![0xFF; 4096] });
+        assert!(!id3::write_tag(&mut file, &tag).unwrap());
+
+        let data = file.get_ref().clone();
+        let audio = after_tag(&original);
+        assert_eq!(data.len(), tag.size() + DEFAULT_PADDING + audio.len());
+        assert!(after_tag(&data) == audio, "{} audio changed", path);
+        assert_eq!(Id3Tag::parse(&data), Some(tag.clone()));
+
+        // The padding now takes a smaller tag in place.
+        let mut file = Cursor::new(data.clone());
+        tag.remove("PRIV");
+        assert!(id3::write_tag(&mut file, &tag).unwrap());
+        assert_eq!(file.get_ref().len(), data.len());
+        assert!(after_tag(file.get_ref()) == audio);
+    }
+}
+
+#[test]
+fn write_without_a_tag() {
+    let original = fs::read("examples/3-note.mp3").unwrap();
+    let audio = after_tag(&original).to_vec();
+    let mut file = Cursor::new(audio.clone());
+
+    let mut tag = Id3Tag::new(Id3Version::V4);
+    tag.set_text("TIT2", "3 notes").unwrap();
+    assert!(!id3::write_tag(&mut file, &tag).unwrap());
+
+    let data = file.get_ref();
+    assert_eq!(data.len(), tag.size() + DEFAULT_PADDING + audio.len());
+    assert!(data[tag.size() + DEFAULT_PADDING..] == audio[..]);
+    assert_eq!(Id3Tag::parse(data), Some(tag));
+}
+
+#[test]
+fn write_with_leading_junk() {
+    let original = fs::read("examples/3-note.mp3").unwrap();
+    let mut data = vec![0x5A; 500];
+    data.extend_from_slice(after_tag(&original));
+    let mut file = Cursor::new(data.clone());
+
+    let mut tag = Id3Tag::new(Id3Version::V3);
+    tag.set_text("TIT2", "3 notes").unwrap();
+    assert!(!id3::write_tag(&mut file, &tag).unwrap());
+
+    let written = file.get_ref();
+    assert_eq!(written.len(), tag.size() + DEFAULT_PADDING + data.len());
+    assert!(written[tag.size() + DEFAULT_PADDING..] == data[..]);
+    assert_eq!(Id3Tag::parse(written), Some(tag));
+}
+
+#[test]
+fn replace_an_oversized_tag() {
+    // A tag of the largest size with a footer, too large to reuse, so the audio moves forward.
+    let original = fs::read("examples/3-note.mp3").unwrap();
+    let audio = after_tag(&original);
+    let mut data = Vec::with_capacity(20 + MAX_TAG_SIZE + audio.len());
+    data.extend_from_slice(b"ID3\x04\x00\x10\x7F\x7F\x7F\x7F");
+    data.resize(10 + MAX_TAG_SIZE, 0);
+    data.extend_from_slice(b"3DI\x04\x00\x10\x7F\x7F\x7F\x7F");
+    data.extend_from_slice(audio);
+    let mut file = Cursor::new(data);
+
+    let mut tag = Id3Tag::new(Id3Version::V4);
+    tag.set_text("TIT2", "3 notes").unwrap();
+    assert!(!id3::write_tag(&mut file, &tag).unwrap());
+
+    let written = file.get_ref();
+    assert_eq!(written.len(), tag.size() + DEFAULT_PADDING + audio.len());
+    assert!(written[tag.size() + DEFAULT_PADDING..] == audio[..]);
+    assert_eq!(Id3Tag::parse(written), Some(tag));
+}
@@ -0,0 +1,42 @@
+// Duration methods reported by `probe` when it can't count every frame.
+
+extern crate mptree;
+
+use std::fs;
+use std::io::Cursor;
+
+use mptree::error::ErrorKind;
+use mptree::probe::{self, DurationMethod, Scan};
+
+#[test]
+fn sampling_no_frames_estimates_from_bitrate() {
+    let data = fs::read("examples/3-note.mp3").unwrap();
+    let counted = probe::probe(&mut Cursor::new(&data[..]), Scan::Full).unwrap();
+    assert_eq!(counted.duration_method(), DurationMethod::FrameCount);
+
+    let info = probe::probe(&mut Cursor::new(&data[..]), Scan::Sample(0)).unwrap();
+    assert_eq!(info.duration_method(), DurationMethod::Bitrate);
+    assert!(!info.duration_method().is_exact());
+    assert_eq!(info.frames(), counted.frames());
+}
+
+#[test]
+fn sampling_no_frames_prefers_the_vbr_header() {
+    let data = fs::read("examples/whatislove.mp3").unwrap();
+    let info = probe::probe(&mut Cursor::new(&data[..]), Scan::Sample(0)).unwrap();
+    assert_eq!(info.duration_method(), DurationMethod::VbrHeader);
+    assert_eq!(info.frames(), 1048);
+}
+
+#[test]
+fn free_format_without_vbr_header_is_an_error() {
+    // MPEG 1 Layer III, free format bitrate, 44.1 kHz, stereo. Frames of 200 bytes.
+    let mut frame = [0u8; 200];
+    frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x00, 0x04]);
+    let data: Vec<u8> = frame.iter().cycle().take(frame.len() * 10).cloned().collect();
+
+    for scan in [Scan::Full, Scan::Sample(5), Scan::Bitrate].iter() {
+        let err = probe::probe(&mut Cursor::new(&data[..]), *scan).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::FreeFormat), "{:?}: {}", scan, err);
+    }
+}
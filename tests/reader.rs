@@ -0,0 +1,125 @@
+// Frame reader behaviour on CRC errors and when starting in the middle of a stream.
+
+extern crate mptree;
+
+use std::fs;
+use std::io::Cursor;
+
+use mptree::decoder::{FrameReader, ReservoirPolicy};
+use mptree::error::ErrorKind;
+use mptree::header::Header;
+use mptree::side_info::{self, SideInformation};
+use mptree::source::{ByteSource, Unseekable};
+
+// MPEG 1 Layer III, 128 kbps, 44.1 kHz, stereo, with a CRC.
+const PROTECTED: [u8; 4] = [0xFF, 0xFA, 0x90, 0x04];
+
+// `count` silent frames with the given header. Their main data is all reservoir.
+fn silent_frames(header_bytes: [u8; 4], count: usize) -> Vec<u8> {
+    let header = Header::new(&header_bytes).unwrap();
+    let side_information = SideInformation::silent(&header);
+    let mut frame = vec![0u8; header.frame_size() as usize];
+    side_info::write_frame_head(&header, &side_information, &mut frame).unwrap();
+
+    let mut stream = Vec::new();
+    for _ in 0..count {
+        stream.extend_from_slice(&frame);
+    }
+    stream
+}
+
+#[test]
+fn crc_mismatch_is_reported_only_when_strict() {
+    let mut stream = silent_frames(PROTECTED, 3);
+    let frame_size = stream.len() / 3;
+    // Break the stored CRC of the second frame.
+    stream[frame_size + 4] ^= 0xFF;
+
+    let mut frame_reader = FrameReader::new(Cursor::new(&stream[..]));
+    for _ in 0..3 {
+        assert!(frame_reader.advance().is_ok());
+    }
+
+    let mut frame_reader = FrameReader::new(Cursor::new(&stream[..]));
+    frame_reader.set_strict_crc(true);
+    assert!(frame_reader.advance().is_ok());
+    let err = frame_reader.advance().unwrap_err();
+    assert!(err.is_recoverable());
+    assert_eq!(err.location().unwrap().offset, frame_size as u64);
+    match *err.kind() {
+        ErrorKind::CrcMismatch { expected, actual } => assert_ne!(expected, actual),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    // Still in sync after the bad frame.
+    let frame = frame_reader.advance().unwrap();
+    assert_eq!(frame.offset(), 2 * frame_size as u64);
+    assert!(frame_reader.advance().unwrap_err().is_eof());
+}
+
+// Offset of the first frame after `skip` frames that takes main data from the frames before it.
+fn reservoir_frame(data: &[u8], skip: u64) -> u64 {
+    let mut frame_reader = FrameReader::new(Cursor::new(data));
+    loop {
+        let frame = frame_reader.advance().unwrap();
+        if frame.number() >= skip && frame.side_information().main_data_begin() > 0 {
+            return frame.offset();
+        }
+    }
+}
+
+#[test]
+fn mid_stream_start_backtracks_when_seekable() {
+    let data = fs::read("examples/whatislove.mp3").unwrap();
+    let start = reservoir_frame(&data, 20);
+
+    let mut cursor = Cursor::new(&data[..]);
+    cursor.set_position(start);
+    let mut frame_reader = FrameReader::new(cursor);
+    let frame = frame_reader.advance().unwrap();
+    assert_eq!(frame.offset(), start);
+    assert!(!frame.silent());
+    assert!(frame.main_data().len() as u32 * 8 >= frame.side_information().main_data_bits());
+    assert_eq!(frame_reader.underflows(), 0);
+}
+
+#[test]
+fn mid_stream_start_falls_back_to_silence_when_not_seekable() {
+    let data = fs::read("examples/whatislove.mp3").unwrap();
+    let start = reservoir_frame(&data, 20);
+
+    let mut frame_reader = FrameReader::new(Unseekable::new(&data[start as usize..]));
+    assert_eq!(frame_reader.reservoir_policy(), ReservoirPolicy::Backtrack);
+    let first = frame_reader.advance().unwrap();
+    assert_eq!(first.offset(), 0);
+    assert!(first.silent());
+    assert!(first.main_data().is_empty());
+
+    // Once the reservoir has filled up again frames decode normally.
+    let mut frames = 1;
+    loop {
+        match frame_reader.advance() {
+            Ok(frame) => {
+                frames += 1;
+                assert_eq!(frame.silent(), frames <= frame_reader.underflows());
+            },
+            Err(err) => {
+                assert!(err.is_eof(), "{}", err);
+                break;
+            },
+        }
+    }
+    assert!(frame_reader.underflows() >= 1);
+    assert!(frames > 100 + frame_reader.underflows());
+}
+
+#[test]
+fn unseekable_source_refuses_to_seek() {
+    let data = [0u8; 16];
+    let mut source = Unseekable::new(&data[..]);
+    let mut buf = [0u8; 4];
+    source.read_exact(&mut buf).unwrap();
+    assert_eq!(source.position().unwrap(), 4);
+    assert!(matches!(*source.seek(0).unwrap_err().kind(), ErrorKind::NotSeekable));
+    assert!(matches!(*source.length().unwrap_err().kind(), ErrorKind::NotSeekable));
+}
@@ -0,0 +1,136 @@
+// Writes the header and side information of every frame in the example files back out and
+// checks the bytes match the originals.
+
+extern crate mptree;
+
+use std::fs;
+use std::io::Cursor;
+
+use mptree::decoder::{Frame, FrameReader};
+use mptree::header::{Header, CHECKSUM_SIZE, HEADER_SIZE};
+use mptree::side_info::{self, SideInformation};
+use mptree::source::Bounded;
+use mptree::tags::Tags;
+
+const EXAMPLES: [&str; 2] = ["examples/whatislove.mp3", "examples/3-note.mp3"];
+
+fn read_frames(path: &str) -> (Vec<u8>, Vec<Frame>) {
+    let data = fs::read(path).unwrap();
+    let mut cursor = Cursor::new(&data[..]);
+    let tags = Tags::locate(&mut cursor).unwrap();
+    let mut frame_reader = FrameReader::new(Bounded::new(&mut cursor, tags.audio().end).unwrap());
+
+    let mut frames = Vec::new();
+    loop {
+        match frame_reader.advance() {
+            Ok(frame) => frames.push(frame),
+            Err(ref err) if err.is_recoverable() => continue,
+            Err(_) => break,
+        }
+    }
+    drop(frame_reader);
+
+    assert!(frames.len() > 100, "{}: only {} frames", path, frames.len());
+    (data, frames)
+}
+
+fn side_info_start(frame: &Frame) -> usize {
+    let mut start = frame.offset() as usize + HEADER_SIZE;
+    if frame.header().protection() {
+        start += CHECKSUM_SIZE;
+    }
+    start
+}
+
+#[test]
+fn header_round_trip() {
+    for path in EXAMPLES.iter() {
+        let (data, frames) = read_frames(path);
+        for frame in &frames {
+            let offset = frame.offset() as usize;
+            let bytes = frame.header().to_bytes();
+            assert_eq!(&bytes[..], &data[offset..offset + HEADER_SIZE], "{}: frame {}", path, frame.number());
+            assert_eq!(&Header::new(&bytes).unwrap(), frame.header());
+        }
+    }
+}
+
+#[test]
+fn header_keeps_unused_mode_extension() {
+    // Stereo, dual channel and mono headers with mode extension bits set.
+    for &byte in [0x34, 0x90, 0xE4, 0xF0].iter() {
+        let bytes = [0xFF, 0xFB, 0x90, byte];
+        assert_eq!(Header::new(&bytes).unwrap().to_bytes(), bytes);
+    }
+}
+
+#[test]
+fn side_info_round_trip() {
+    for path in EXAMPLES.iter() {
+        let (data, frames) = read_frames(path);
+        for frame in &frames {
+            let start = side_info_start(frame);
+            let mut written = [0u8; 32];
+            let length = frame.side_information().write(&mut written).unwrap();
+            assert_eq!(length, side_info::size(frame.header()));
+            assert_eq!(&written[..length], &data[start..start + length], "{}: frame {}", path, frame.number());
+
+            let parsed = SideInformation::new(frame.header(), &written[..length]).unwrap();
+            assert_eq!(parsed.main_data_begin(), frame.side_information().main_data_begin());
+            assert_eq!(parsed.main_data_bits(), frame.side_information().main_data_bits());
+        }
+    }
+}
+
+#[test]
+fn frame_head_round_trip() {
+    for path in EXAMPLES.iter() {
+        let (data, frames) = read_frames(path);
+        for frame in &frames {
+            let offset = frame.offset() as usize;
+            let mut written = [0u8; HEADER_SIZE + CHECKSUM_SIZE + 32];
+            let length = side_info::write_frame_head(frame.header(), frame.side_information(), &mut written).unwrap();
+            assert_eq!(&written[..length], &data[offset..offset + length], "{}: frame {}", path, frame.number());
+        }
+    }
+}
+
+#[test]
+fn frame_head_recomputes_crc() {
+    for path in EXAMPLES.iter() {
+        let (data, frames) = read_frames(path);
+        for frame in frames.iter().filter(|frame| !frame.header().protection()) {
+            // The same frame with the protection bit set, so a CRC has to be written.
+            let mut header_bytes = frame.header().to_bytes();
+            header_bytes[1] &= 0xFE;
+            let header = Header::new(&header_bytes).unwrap();
+
+            let mut written = [0u8; HEADER_SIZE + CHECKSUM_SIZE + 32];
+            let length = side_info::write_frame_head(&header, frame.side_information(), &mut written).unwrap();
+            let side_info_length = side_info::size(&header);
+            assert_eq!(length, HEADER_SIZE + CHECKSUM_SIZE + side_info_length);
+
+            let start = side_info_start(frame);
+            let side_info_data = &written[HEADER_SIZE + CHECKSUM_SIZE..length];
+            assert_eq!(side_info_data, &data[start..start + side_info_length]);
+
+            let checksum = u16::from_be_bytes([written[HEADER_SIZE], written[HEADER_SIZE + 1]]);
+            assert!(SideInformation::checksum(&header_bytes, side_info_data, checksum));
+        }
+    }
+}
+
+#[test]
+fn crc_known_answers() {
+    // The CRC-16/CMS check value, which is the same CRC without the header split.
+    assert_eq!(side_info::crc(b"12", b"3456789"), 0xAEE7);
+
+    // MPEG 1 Layer III, 128 kbps, 44.1 kHz, stereo, protected, with all zero side information.
+    let header_bytes = [0xFF, 0xFA, 0x90, 0x04];
+    let header = Header::new(&header_bytes).unwrap();
+    let mut written = [0u8; HEADER_SIZE + CHECKSUM_SIZE + 32];
+    side_info::write_frame_head(&header, &SideInformation::silent(&header), &mut written).unwrap();
+    assert_eq!(&written[..HEADER_SIZE + CHECKSUM_SIZE], &[0xFF, 0xFA, 0x90, 0x04, 0x41, 0xEF]);
+    assert!(SideInformation::checksum(&header_bytes, &[0; 32], 0x41EF));
+    assert!(!SideInformation::checksum(&header_bytes, &[0; 32], 0x41EE));
+}
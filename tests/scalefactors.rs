@@ -0,0 +1,145 @@
+// MPEG 2 scalefactor partitions, on side information and main data written bit by bit.
+
+extern crate mptree;
+
+use mptree::bits::BitWriter;
+use mptree::error::ErrorKind;
+use mptree::header::Header;
+use mptree::scalefactors;
+use mptree::side_info::SideInformation;
+
+// MPEG 2 Layer III, 64 kbps, 22.05 kHz, no CRC.
+const MONO: [u8; 4] = [0xFF, 0xF3, 0x80, 0xC0];
+const INTENSITY_STEREO: [u8; 4] = [0xFF, 0xF3, 0x80, 0x60];
+
+// One channel's side information: part2_3_length, scalefactor_compress and, for short blocks,
+// whether the block is mixed.
+struct Channel {
+    length: u32,
+    compress: u32,
+    short: Option<bool>,
+}
+
+fn write_side_information(header: &Header, channels: &[Channel]) -> SideInformation {
+    let mut data = [0u8; 17];
+    {
+        let mut writer = BitWriter::new(&mut data);
+        writer.write(8, 0).unwrap(); // main_data_begin
+        writer.write(channels.len() as u32, 0).unwrap(); // private bits
+        for channel in channels {
+            writer.write(12, channel.length).unwrap();
+            writer.write(9, 0).unwrap(); // big_values
+            writer.write(8, 210).unwrap(); // global_gain
+            writer.write(9, channel.compress).unwrap();
+            match channel.short {
+                Some(mixed) => {
+                    writer.write_bit(true).unwrap();
+                    writer.write(2, 2).unwrap();
+                    writer.write_bit(mixed).unwrap();
+                    writer.write(10 + 9, 0).unwrap(); // table_select, subblock_gain
+                },
+                None => {
+                    writer.write_bit(false).unwrap();
+                    writer.write(15 + 7, 0).unwrap(); // table_select, region counts
+                },
+            }
+            writer.write(2, 0).unwrap(); // scalefactor_scale, count1table_select
+        }
+    }
+    SideInformation::new(header, &data).unwrap()
+}
+
+// Writes `count` scalefactors of each width in turn, each one its index masked to the width.
+fn main_data(partitions: &[(usize, u32)]) -> (Vec<u8>, Vec<u8>) {
+    let mut data = vec![0u8; 64];
+    let mut values = Vec::new();
+    {
+        let mut writer = BitWriter::new(&mut data);
+        for &(count, bits) in partitions {
+            for _ in 0..count {
+                let value = values.len() as u32 & ((1 << bits) - 1);
+                writer.write(bits, value).unwrap();
+                values.push(value as u8);
+            }
+        }
+    }
+    (data, values)
+}
+
+#[test]
+fn long_block() {
+    let header = Header::new(&MONO).unwrap();
+    // slen 1, 2, 3, 0 with the first partition table.
+    let side_information = write_side_information(&header, &[Channel { length: 31, compress: 7 << 4 | 3 << 2, short: None }]);
+    let (data, values) = main_data(&[(6, 1), (5, 2), (5, 3), (5, 0)]);
+
+    let scalefactors = scalefactors::read(&header, &side_information, &data).unwrap();
+    let scalefactors = scalefactors[0][0];
+    assert_eq!(scalefactors.length(), 31);
+    assert_eq!(&scalefactors.long()[..21], &values[..]);
+    assert_eq!(scalefactors.long()[21], 0);
+    assert_eq!(scalefactors.short(), &[[0; 3]; 13]);
+}
+
+#[test]
+fn short_and_mixed_blocks() {
+    let header = Header::new(&MONO).unwrap();
+    // compress >= 500 selects the third table: slen 2, 1 over 18 and 18 short scalefactors.
+    let side_information = write_side_information(&header, &[Channel { length: 54, compress: 500 + 2 * 3 + 1, short: Some(false) }]);
+    let (data, values) = main_data(&[(18, 2), (18, 1)]);
+
+    let scalefactors = scalefactors::read(&header, &side_information, &data).unwrap()[0][0];
+    assert_eq!(scalefactors.length(), 54);
+    for (index, &value) in values.iter().enumerate() {
+        assert_eq!(scalefactors.short()[index / 3][index % 3], value);
+    }
+    assert_eq!(scalefactors.long(), &[0; 22]);
+
+    // Mixed: 6 long bands, then short bands from band 3.
+    let side_information = write_side_information(&header, &[Channel { length: 51, compress: 7 << 4 | 3 << 2, short: Some(true) }]);
+    let (data, values) = main_data(&[(6, 1), (9, 2), (9, 3), (9, 0)]);
+
+    let scalefactors = scalefactors::read(&header, &side_information, &data).unwrap()[0][0];
+    assert_eq!(scalefactors.length(), 6 + 18 + 27);
+    assert_eq!(&scalefactors.long()[..6], &values[..6]);
+    assert_eq!(&scalefactors.long()[6..], &[0; 16]);
+    for (index, &value) in values[6..].iter().enumerate() {
+        assert_eq!(scalefactors.short()[3 + index / 3][index % 3], value);
+    }
+    assert_eq!(scalefactors.short()[..3], [[0; 3]; 3]);
+}
+
+#[test]
+fn intensity_stereo_right_channel() {
+    let header = Header::new(&INTENSITY_STEREO).unwrap();
+    // The left channel uses the usual tables, the right one halves scalefactor_compress and
+    // picks from the intensity tables: slen 1, 2, 3 over 7 long bands each.
+    let side_information = write_side_information(&header, &[
+        Channel { length: 31, compress: 7 << 4 | 3 << 2, short: None },
+        Channel { length: 42, compress: (36 + 2 * 6 + 3) << 1, short: None },
+    ]);
+    let (data, values) = main_data(&[(6, 1), (5, 2), (5, 3), (5, 0), (7, 1), (7, 2), (7, 3)]);
+
+    let scalefactors = scalefactors::read(&header, &side_information, &data).unwrap()[0];
+    assert_eq!(scalefactors[0].length(), 31);
+    assert_eq!(&scalefactors[0].long()[..21], &values[..21]);
+    assert_eq!(scalefactors[1].length(), 42);
+    assert_eq!(&scalefactors[1].long()[..21], &values[21..]);
+    assert!(!side_information.granules()[0].channel(1).preflag());
+}
+
+#[test]
+fn overruns() {
+    let header = Header::new(&MONO).unwrap();
+    let (data, _) = main_data(&[(6, 1), (5, 2), (5, 3), (5, 0)]);
+
+    // part2_3_length reaches past the main data.
+    let side_information = write_side_information(&header, &[Channel { length: 64 * 8 + 1, compress: 7 << 4 | 3 << 2, short: None }]);
+    let err = scalefactors::read(&header, &side_information, &data).unwrap_err();
+    assert!(matches!(*err.kind(), ErrorKind::MainDataOverrun));
+
+    // The 31 bits of scalefactors don't fit in part2_3_length.
+    let side_information = write_side_information(&header, &[Channel { length: 30, compress: 7 << 4 | 3 << 2, short: None }]);
+    let err = scalefactors::read(&header, &side_information, &data).unwrap_err();
+    assert!(matches!(*err.kind(), ErrorKind::ScalefactorOverrun));
+}
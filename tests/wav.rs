@@ -0,0 +1,185 @@
+// Checks the RIFF layout `WavWriter` produces for each sample format and channel count.
+
+extern crate mptree;
+
+use std::io::Cursor;
+
+use mptree::error::ErrorKind;
+use mptree::wav::{SampleFormat, WavSpec, WavWriter};
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+// Chunk ids with the offset and size of their bodies, after checking the RIFF header.
+fn chunks(data: &[u8]) -> Vec<([u8; 4], usize, usize)> {
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(u32_at(data, 4) as usize, data.len() - 8);
+    assert_eq!(&data[8..12], b"WAVE");
+
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset < data.len() {
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&data[offset..offset + 4]);
+        let size = u32_at(data, offset + 4) as usize;
+        chunks.push((id, offset + 8, size));
+        offset += 8 + size + size % 2;
+    }
+    assert_eq!(offset, data.len());
+    chunks
+}
+
+fn chunk<'a>(data: &'a [u8], id: &[u8; 4]) -> Option<&'a [u8]> {
+    chunks(data).into_iter()
+        .find(|chunk| &chunk.0 == id)
+        .map(|(_, offset, size)| &data[offset..offset + size])
+}
+
+fn write(spec: WavSpec, samples: &[f32]) -> Vec<u8> {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+    writer.write_samples(samples).unwrap();
+    let cursor = writer.finish().unwrap();
+    assert_eq!(cursor.position() as usize, cursor.get_ref().len());
+    cursor.into_inner()
+}
+
+#[test]
+fn pcm_format() {
+    let data = write(WavSpec::new(2, 44100, SampleFormat::Int16), &[0.0, 0.5, -0.5, 0.25, 1.0, -1.0]);
+    let ids: Vec<[u8; 4]> = chunks(&data).iter().map(|chunk| chunk.0).collect();
+    assert_eq!(ids, vec![*b"fmt ", *b"data"]);
+
+    let fmt = chunk(&data, b"fmt ").unwrap();
+    assert_eq!(fmt.len(), 16);
+    assert_eq!(u16_at(fmt, 0), 0x0001);
+    assert_eq!(u16_at(fmt, 2), 2);
+    assert_eq!(u32_at(fmt, 4), 44100);
+    assert_eq!(u32_at(fmt, 8), 44100 * 4);
+    assert_eq!(u16_at(fmt, 12), 4);
+    assert_eq!(u16_at(fmt, 14), 16);
+
+    assert_eq!(chunk(&data, b"data").unwrap().len(), 12);
+    assert_eq!(data.len(), 44 + 12);
+}
+
+#[test]
+fn float_format_has_fact_chunk() {
+    let data = write(WavSpec::new(1, 22050, SampleFormat::Float32), &[0.0, 0.5, -0.75]);
+    let ids: Vec<[u8; 4]> = chunks(&data).iter().map(|chunk| chunk.0).collect();
+    assert_eq!(ids, vec![*b"fmt ", *b"fact", *b"data"]);
+
+    let fmt = chunk(&data, b"fmt ").unwrap();
+    assert_eq!(fmt.len(), 16);
+    assert_eq!(u16_at(fmt, 0), 0x0003);
+    assert_eq!(u16_at(fmt, 12), 4);
+    assert_eq!(u16_at(fmt, 14), 32);
+
+    let fact = chunk(&data, b"fact").unwrap();
+    assert_eq!(fact.len(), 4);
+    assert_eq!(u32_at(fact, 0), 3);
+
+    let samples = chunk(&data, b"data").unwrap();
+    assert_eq!(samples.len(), 12);
+    assert_eq!(f32::from_le_bytes([samples[8], samples[9], samples[10], samples[11]]), -0.75);
+}
+
+#[test]
+fn extensible_format() {
+    // More than 16 bit integer samples, even in mono.
+    assert!(WavSpec::new(1, 48000, SampleFormat::Int24).extensible());
+    // More than two channels.
+    assert!(WavSpec::new(6, 48000, SampleFormat::Int16).extensible());
+    assert!(WavSpec::new(6, 48000, SampleFormat::Float32).extensible());
+    assert!(!WavSpec::new(2, 48000, SampleFormat::Int16).extensible());
+    assert!(!WavSpec::new(2, 48000, SampleFormat::Float32).extensible());
+
+    for &(channels, sample_format, tag, mask) in [
+        (1, SampleFormat::Int24, 0x0001, 0x01),
+        (6, SampleFormat::Int16, 0x0001, 0x3F),
+        (6, SampleFormat::Float32, 0x0003, 0x3F),
+    ].iter() {
+        let spec = WavSpec::new(channels, 48000, sample_format);
+        let data = write(spec, &vec![0.25; channels as usize]);
+
+        let fmt = chunk(&data, b"fmt ").unwrap();
+        assert_eq!(fmt.len(), 40);
+        assert_eq!(u16_at(fmt, 0), 0xFFFE);
+        assert_eq!(u16_at(fmt, 2), channels);
+        assert_eq!(u16_at(fmt, 12), spec.block_align());
+        assert_eq!(u16_at(fmt, 14), sample_format.bits());
+        assert_eq!(u16_at(fmt, 16), 22);
+        assert_eq!(u16_at(fmt, 18), sample_format.bits());
+        assert_eq!(u32_at(fmt, 20), mask);
+        assert_eq!(u16_at(fmt, 24), tag);
+        assert_eq!(&fmt[26..40], &[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+        assert_eq!(chunk(&data, b"fact").is_some(), sample_format == SampleFormat::Float32);
+    }
+}
+
+#[test]
+fn finish_fills_in_sizes_and_pads() {
+    // A single 24 bit mono sample leaves an odd length data chunk.
+    let data = write(WavSpec::new(1, 8000, SampleFormat::Int24), &[0.5]);
+    let (_, offset, size) = *chunks(&data).last().unwrap();
+    assert_eq!(size, 3);
+    assert_eq!(offset + 4, data.len());
+    assert_eq!(data[data.len() - 1], 0);
+    assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+
+    let data = write(WavSpec::new(2, 8000, SampleFormat::Int16), &[0.0; 10]);
+    assert_eq!(u32_at(&data, 4), 36 + 20);
+    assert_eq!(u32_at(&data, 40), 20);
+    assert_eq!(data.len(), 44 + 20);
+}
+
+#[test]
+fn quantize_clips_at_full_scale() {
+    let data = write(WavSpec::new(1, 8000, SampleFormat::Int16), &[1.0, -1.0, 2.0, -2.0, 0.5, -0.5, 0.0]);
+    let samples: Vec<i16> = chunk(&data, b"data").unwrap()
+        .chunks(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    assert_eq!(samples, vec![32767, -32768, 32767, -32768, 16384, -16384, 0]);
+
+    let data = write(WavSpec::new(1, 8000, SampleFormat::Int24), &[1.0, -1.0, 1.5, -1.5]);
+    let samples: Vec<i32> = chunk(&data, b"data").unwrap()
+        .chunks(3)
+        .map(|bytes| i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+        .collect();
+    assert_eq!(samples, vec![8388607, -8388608, 8388607, -8388608]);
+}
+
+#[test]
+fn size_limit() {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), WavSpec::new(1, 8000, SampleFormat::Int16)).unwrap();
+    assert_eq!(writer.max_size(), u32::MAX);
+
+    // "WAVE", the format chunk and the data chunk header take 36 bytes of the RIFF size.
+    writer.set_max_size(36 + 4);
+    writer.write_samples(&[0.1, 0.2]).unwrap();
+    let err = writer.write_sample(0.3).unwrap_err();
+    assert!(matches!(*err.kind(), ErrorKind::RiffTooLarge));
+    assert_eq!(writer.frames(), 2);
+
+    let data = writer.finish().unwrap().into_inner();
+    assert_eq!(data.len(), 44 + 4);
+    assert_eq!(chunk(&data, b"data").unwrap().len(), 4);
+}
+
+#[test]
+fn bad_spec() {
+    for &spec in [
+        WavSpec::new(0, 44100, SampleFormat::Int16),
+        WavSpec::new(2, 0, SampleFormat::Int16),
+        WavSpec::new(u16::MAX, 44100, SampleFormat::Float32),
+        WavSpec::new(8, u32::MAX, SampleFormat::Int24),
+    ].iter() {
+        let err = WavWriter::new(Cursor::new(Vec::new()), spec).err().unwrap();
+        assert!(matches!(*err.kind(), ErrorKind::BadWavSpec), "{:?}", spec);
+    }
+}